@@ -0,0 +1,327 @@
+use std::collections::{HashMap, VecDeque};
+use std::sync::{Condvar, Mutex};
+use std::time::{Duration, Instant};
+
+/// How long a flow keeps its place after its last grant came due. Transfers
+/// ask for their next chunk right after sending the last one, so a flow that
+/// has nothing waiting for this long has stopped and gives up its share.
+const RETURN_GRACE: Duration = Duration::from_millis(20);
+
+/// Paces every transfer through one token bucket, so the bytes they move
+/// together never exceed `rate` per second plus one `burst`, however clients
+/// come and go.
+///
+/// The order in which transfers get the bucket is decided by deficit round
+/// robin over flows, one per client, so each client moves bytes in
+/// proportion to its weight. Flows that stop asking drop out of the round,
+/// leaving their share to the others.
+#[derive(Debug)]
+pub struct Scheduler {
+    /// Bytes per second; 0 means unlimited.
+    rate: u64,
+    burst: u64,
+    state: Mutex<State>,
+    /// Signalled on every new request and grant.
+    changed: Condvar,
+}
+
+#[derive(Debug)]
+struct State {
+    /// Negative while the last grant is still being paid for.
+    tokens: f64,
+    refilled: Instant,
+    /// Active flows, each of them in `round` exactly once.
+    flows: HashMap<u64, Flow>,
+    /// The front flow is having its turn while `in_turn` is set.
+    round: VecDeque<u64>,
+    in_turn: bool,
+    /// Credit a weight of 1 earns per turn: the largest request seen, so
+    /// every flow can move at least one request per turn.
+    quantum: u64,
+    next_ticket: u64,
+    /// Requests picked to go next, and when the bucket has paid for them.
+    granted: HashMap<u64, Instant>,
+}
+
+#[derive(Debug)]
+struct Flow {
+    weight: u64,
+    deficit: u64,
+    /// Tickets and sizes of the flow's waiting requests, oldest first.
+    waiting: VecDeque<(u64, u64)>,
+    /// When the flow's last grant came due.
+    due: Instant,
+}
+
+/// What the round robin wants to do next.
+enum Pick {
+    Grant { flow: u64, ticket: u64, bytes: u64 },
+    /// The flow having its turn may still come back for more until then.
+    WaitFor(Instant),
+    Idle,
+}
+
+impl Scheduler {
+    /// Starts with a full bucket, so the first `burst` bytes go out at once.
+    /// With no burst, every byte waits its turn at `rate`.
+    pub fn new(rate: u64, burst: u64) -> Scheduler {
+        let state = State {
+            tokens: burst as f64,
+            refilled: Instant::now(),
+            flows: HashMap::new(),
+            round: VecDeque::new(),
+            in_turn: false,
+            quantum: 0,
+            next_ticket: 0,
+            granted: HashMap::new(),
+        };
+        Scheduler { rate, burst, state: Mutex::new(state), changed: Condvar::new() }
+    }
+
+    pub fn rate(&self) -> u64 {
+        self.rate
+    }
+
+    /// Waits until `flow` gets its turn to move `bytes`, and returns when
+    /// the bucket will have paid for them. While both keep asking, a flow of
+    /// weight 2 moves twice the bytes of a flow of weight 1, whatever size
+    /// their requests are. The caller sleeps until then, so that it can hold
+    /// turns in several schedulers at once and wait for the last of them.
+    pub fn reserve(&self, flow: u64, weight: u32, bytes: usize) -> Instant {
+        if self.rate == 0 || bytes == 0 {
+            return Instant::now();
+        }
+        let mut state = self.state.lock().unwrap();
+        let ticket = state.enqueue(flow, weight, bytes as u64);
+        self.changed.notify_all();
+
+        loop {
+            if let Some(ready) = state.granted.remove(&ticket) {
+                return ready;
+            }
+
+            let now = Instant::now();
+            let refill = now.duration_since(state.refilled).as_secs_f64() * self.rate as f64;
+            state.tokens = (state.tokens + refill).min(self.burst as f64);
+            state.refilled = now;
+
+            // The next request is picked once the previous one is paid for
+            let wait = if state.tokens < 0.0 {
+                Duration::from_secs_f64(-state.tokens / self.rate as f64)
+            } else {
+                match state.next_request(now) {
+                    Pick::Grant { flow, ticket, bytes } => {
+                        state.tokens -= bytes as f64;
+                        let debt = (-state.tokens).max(0.0) / self.rate as f64;
+                        let ready = now + Duration::from_secs_f64(debt);
+                        if let Some(flow) = state.flows.get_mut(&flow) {
+                            flow.due = ready;
+                        }
+                        state.granted.insert(ticket, ready);
+                        self.changed.notify_all();
+                        continue;
+                    }
+                    Pick::WaitFor(until) => until.saturating_duration_since(now),
+                    Pick::Idle => RETURN_GRACE,
+                }
+            };
+            state = self.changed.wait_timeout(state, wait).unwrap().0;
+        }
+    }
+}
+
+impl State {
+    fn enqueue(&mut self, flow: u64, weight: u32, bytes: u64) -> u64 {
+        self.next_ticket += 1;
+        self.quantum = self.quantum.max(bytes);
+        let round = &mut self.round;
+        let entry = self.flows.entry(flow).or_insert_with(|| {
+            round.push_back(flow);
+            Flow { weight: 0, deficit: 0, waiting: VecDeque::new(), due: Instant::now() }
+        });
+        entry.weight = u64::from(weight.max(1));
+        entry.waiting.push_back((self.next_ticket, bytes));
+        self.next_ticket
+    }
+
+    /// Each turn adds `quantum` times the flow's weight to its deficit, and
+    /// the flow is served for as long as its requests fit in it. Unspent
+    /// credit carries over to the next turn but not past leaving the round.
+    fn next_request(&mut self, now: Instant) -> Pick {
+        loop {
+            let Some(&id) = self.round.front() else {
+                return Pick::Idle;
+            };
+            let quantum = self.quantum;
+            let Some(flow) = self.flows.get_mut(&id) else {
+                self.round.pop_front();
+                self.in_turn = false;
+                continue;
+            };
+            if !self.in_turn {
+                flow.deficit += quantum * flow.weight;
+                self.in_turn = true;
+            }
+
+            match flow.waiting.front().copied() {
+                Some((ticket, bytes)) if bytes <= flow.deficit => {
+                    flow.deficit -= bytes;
+                    flow.waiting.pop_front();
+                    return Pick::Grant { flow: id, ticket, bytes };
+                }
+                Some(_) => {
+                    self.round.rotate_left(1);
+                    self.in_turn = false;
+                }
+                None if now < flow.due + RETURN_GRACE => return Pick::WaitFor(flow.due + RETURN_GRACE),
+                None => {
+                    self.flows.remove(&id);
+                    self.round.pop_front();
+                    self.in_turn = false;
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+    use std::thread;
+
+    impl Scheduler {
+        fn acquire(&self, flow: u64, weight: u32, bytes: usize) {
+            let ready = self.reserve(flow, weight, bytes);
+            thread::sleep(ready.saturating_duration_since(Instant::now()));
+        }
+    }
+
+    #[test]
+    fn test_burst_is_free() {
+        let scheduler = Scheduler::new(1000, 500);
+        let start = Instant::now();
+        scheduler.acquire(1, 1, 300);
+        scheduler.acquire(1, 1, 200);
+        // Paced, the same bytes would take 500ms
+        assert!(start.elapsed() < Duration::from_millis(250));
+    }
+
+    #[test]
+    fn test_rate_is_enforced() {
+        let scheduler = Scheduler::new(1000, 100);
+        let start = Instant::now();
+        // 100 from the burst, the remaining 400 at 1000 bytes/s
+        for _ in 0..5 {
+            scheduler.acquire(1, 1, 100);
+        }
+        let elapsed = start.elapsed();
+        assert!(elapsed >= Duration::from_millis(380), "Too fast: {:?}", elapsed);
+        assert!(elapsed < Duration::from_millis(1000), "Too slow: {:?}", elapsed);
+    }
+
+    #[test]
+    fn test_rate_is_shared() {
+        let scheduler = Scheduler::new(2000, 100);
+        let start = Instant::now();
+        thread::scope(|scope| {
+            for flow in 0..4 {
+                let scheduler = &scheduler;
+                scope.spawn(move || {
+                    for _ in 0..5 {
+                        scheduler.acquire(flow, 1, 50);
+                    }
+                });
+            }
+        });
+        // 1000 bytes in total, 900 of them at 2000 bytes/s
+        let elapsed = start.elapsed();
+        assert!(elapsed >= Duration::from_millis(430), "Too fast: {:?}", elapsed);
+        assert!(elapsed < Duration::from_millis(1200), "Too slow: {:?}", elapsed);
+    }
+
+    #[test]
+    fn test_no_burst() {
+        let scheduler = Scheduler::new(1000, 0);
+        let start = Instant::now();
+        scheduler.acquire(1, 1, 200);
+        let elapsed = start.elapsed();
+        assert!(elapsed >= Duration::from_millis(190), "Too fast: {:?}", elapsed);
+        assert!(elapsed < Duration::from_millis(600), "Too slow: {:?}", elapsed);
+    }
+
+    #[test]
+    fn test_reservations_overlap() {
+        let (first, second) = (Scheduler::new(1000, 0), Scheduler::new(1000, 0));
+        let start = Instant::now();
+        for _ in 0..4 {
+            let ready = first.reserve(1, 1, 100).max(second.reserve(1, 1, 100));
+            thread::sleep(ready.saturating_duration_since(Instant::now()));
+        }
+        // Waiting on both takes as long as waiting on one, not twice as long
+        let elapsed = start.elapsed();
+        assert!(elapsed >= Duration::from_millis(390), "Too fast: {:?}", elapsed);
+        assert!(elapsed < Duration::from_millis(700), "Waits added up: {:?}", elapsed);
+    }
+
+    #[test]
+    fn test_unlimited() {
+        let scheduler = Scheduler::new(0, 0);
+        let start = Instant::now();
+        scheduler.acquire(1, 1 << 30, 1 << 30);
+        assert!(start.elapsed() < Duration::from_millis(250));
+    }
+
+    /// Bytes each `(flow, weight, request size)` moved in `duration`, with
+    /// every flow asking again as soon as it is served.
+    fn contend(scheduler: &Scheduler, flows: &[(u64, u32, usize)], duration: Duration) -> Vec<usize> {
+        let stop = AtomicBool::new(false);
+        let moved: Vec<AtomicUsize> = flows.iter().map(|_| AtomicUsize::new(0)).collect();
+        thread::scope(|scope| {
+            for (&(flow, weight, bytes), moved) in flows.iter().zip(&moved) {
+                let stop = &stop;
+                scope.spawn(move || {
+                    while !stop.load(Ordering::SeqCst) {
+                        scheduler.acquire(flow, weight, bytes);
+                        moved.fetch_add(bytes, Ordering::SeqCst);
+                    }
+                });
+            }
+            thread::sleep(duration);
+            stop.store(true, Ordering::SeqCst);
+        });
+        moved.iter().map(|moved| moved.load(Ordering::SeqCst)).collect()
+    }
+
+    #[test]
+    fn test_shares_are_fair_in_bytes() {
+        let scheduler = Scheduler::new(20_000, 0);
+        // Asking in smaller pieces earns no less
+        let moved = contend(&scheduler, &[(1, 1, 400), (2, 1, 100)], Duration::from_secs(1));
+        let ratio = moved[0] as f64 / moved[1] as f64;
+        assert!((0.75..1.34).contains(&ratio), "Unfair split: {:?}", moved);
+    }
+
+    #[test]
+    fn test_weights() {
+        let scheduler = Scheduler::new(20_000, 0);
+        let moved = contend(&scheduler, &[(1, 1, 200), (2, 3, 200)], Duration::from_secs(1));
+        let ratio = moved[1] as f64 / moved[0] as f64;
+        assert!((2.4..3.75).contains(&ratio), "Weights not honoured: {:?}", moved);
+    }
+
+    #[test]
+    fn test_idle_share_is_redistributed() {
+        let scheduler = Scheduler::new(10_000, 0);
+        contend(&scheduler, &[(1, 1, 100), (2, 1, 100)], Duration::from_millis(200));
+        // The first flow stops asking, so the second gets the whole rate
+        let start = Instant::now();
+        for _ in 0..10 {
+            scheduler.acquire(2, 1, 500);
+        }
+        let elapsed = start.elapsed();
+        assert!(elapsed >= Duration::from_millis(480), "Too fast: {:?}", elapsed);
+        // Holding its share, the first flow would have made it 1000ms
+        assert!(elapsed < Duration::from_millis(800), "Idle flow held its share: {:?}", elapsed);
+    }
+}
@@ -1,115 +1,674 @@
-use std::fs::{File, OpenOptions, create_dir_all};
-use std::io::{self, Read, Write, BufRead, BufReader, BufWriter, Seek, SeekFrom};
-use std::net::{TcpListener, TcpStream};
-use std::sync::atomic::{AtomicUsize, Ordering};
+mod limiter;
+
+use std::ffi::{OsStr, OsString};
+use std::fs::{File, OpenOptions, create_dir_all, read_dir, remove_dir, remove_file, rename};
+use std::io::{self, BufRead, Read, Write, BufReader, BufWriter, Seek, SeekFrom};
+use std::net::{IpAddr, Shutdown, SocketAddr, TcpListener, TcpStream, ToSocketAddrs};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
+use std::collections::{BTreeMap, BTreeSet, HashMap, VecDeque};
+use std::sync::{Arc, Condvar, Mutex, MutexGuard, OnceLock};
 use std::{thread, env, process};
-use std::time::Duration;
-use shared_lib::{GetError, normalize_path, debug_eprintln, debug_println};
+use std::time::{Duration, Instant};
+use shared_lib::checksum::{crc32c, Sha256};
+use limiter::Scheduler;
+use shared_lib::glob::glob_match;
+use shared_lib::mux::{read_frame, Demux, StreamReader, StreamWriter, MAX_STREAMS};
+use shared_lib::{
+    CAPABILITIES, CAP_CHECKSUMS, CAP_MUX, CAP_SESSION, PROTOCOL_VERSION, BusyHint, FileStat, GetError, Request, Response, has_capability,
+    negotiate_capabilities, os_str_bytes, resolve_in_root, debug_eprintln, debug_println,
+};
 
-static mut TRANSFER_RATE: usize = 256;
+/// Bytes per second served to downloads, all clients together.
+static mut DOWNLOAD_RATE: usize = 256;
+/// Bytes per second accepted from uploads, all clients together.
+static mut UPLOAD_RATE: usize = 256;
+/// Bytes that may move at once after a quiet spell, on top of either rate;
+/// 0 paces every chunk.
+static mut BURST: usize = 0;
 static mut MAX_CLIENTS: usize = 5;
 static ACTIVE_CLIENTS: AtomicUsize = AtomicUsize::new(0);
+/// Bytes sent to downloads so far, all clients together.
+static DOWNLOADED: AtomicU64 = AtomicU64::new(0);
+/// Bytes received from uploads so far, all clients together.
+static UPLOADED: AtomicU64 = AtomicU64::new(0);
+static mut PARTIAL_MAX_AGE: u64 = 24 * 60 * 60;
+static mut IDLE_TIMEOUT: u64 = 60;
+/// Seconds a new connection has to send its `HELLO`.
+static mut CONNECT_TIMEOUT: u64 = 10;
+/// Seconds a transfer may stall on one chunk, in either direction.
+static mut CHUNK_TIMEOUT: u64 = 30;
+/// Connections that may wait for a slot; 0 turns them away at once.
+static mut QUEUE_SIZE: usize = 0;
+/// Seconds a queued connection waits before it is told the server is busy.
+static mut QUEUE_TIMEOUT: u64 = 30;
+/// Seconds between lines of transfer statistics; 0 turns them off.
+static mut STATS_INTERVAL: u64 = 60;
+static ROOT_DIR: OnceLock<PathBuf> = OnceLock::new();
+static DOWNLOADS: OnceLock<Scheduler> = OnceLock::new();
+static UPLOADS: OnceLock<Scheduler> = OnceLock::new();
+/// `--weight` rules by address, in the order they were given.
+static WEIGHTS: OnceLock<Vec<(ClientMatch, u32)>> = OnceLock::new();
+/// `--max-connections` rules; every one that covers a client applies.
+static CONNECTION_LIMITS: OnceLock<Vec<(ClientMatch, usize)>> = OnceLock::new();
+/// `--max-rate` rules; every one that covers a client applies.
+static RATE_LIMITS: OnceLock<Vec<(ClientMatch, u64)>> = OnceLock::new();
+/// Connections open per address.
+static CONNECTIONS: Mutex<BTreeMap<ClientMatch, usize>> = Mutex::new(BTreeMap::new());
+/// Schedulers shared by the clients under each `--max-rate` rule, by address.
+static CAPS: Mutex<BTreeMap<ClientMatch, Arc<Scheduler>>> = Mutex::new(BTreeMap::new());
+/// Scheduler flows by address, shared by all of a client's connections.
+static FLOWS: Mutex<BTreeMap<ClientMatch, Arc<u64>>> = Mutex::new(BTreeMap::new());
+static NEXT_FLOW: AtomicU64 = AtomicU64::new(0);
+/// Destinations with an upload running. Each has a single partial file,
+/// which only one upload may write at a time.
+static ACTIVE_UPLOADS: Mutex<BTreeSet<PathBuf>> = Mutex::new(BTreeSet::new());
+
+/// Address served when no `--listen` is given.
+const DEFAULT_LISTEN: &str = "127.0.0.1:7878";
+
+/// Suffix of the hidden files uploads are written to before being renamed.
+const PARTIAL_SUFFIX: &str = ".remcp-part";
+
+/// Chunk size when a rate of 0 lifts the limit.
+const UNLIMITED_CHUNK_SIZE: usize = 64 * 1024;
+
+/// Assumed length of a client's stay until one has been measured.
+const DEFAULT_SESSION: Duration = Duration::from_secs(5);
+/// Longest wait a busy client is told to expect.
+const MAX_RETRY_AFTER: u64 = 60;
+
+static BUSY: Mutex<BusyTracker> = Mutex::new(BusyTracker::new());
+
+/// How often a queued client hears its position even when it has not moved.
+const QUEUE_UPDATE: Duration = Duration::from_secs(2);
+
+static QUEUE: Mutex<WaitQueue> = Mutex::new(WaitQueue::new());
+/// Signalled whenever a slot frees up or the queue changes.
+static QUEUE_CHANGED: Condvar = Condvar::new();
+
+/// Connections waiting for a client slot, in arrival order. Slots are only
+/// handed out under its lock, so a newcomer cannot overtake the queue.
+struct WaitQueue {
+    next_ticket: u64,
+    waiting: VecDeque<u64>,
+}
+
+impl WaitQueue {
+    const fn new() -> WaitQueue {
+        WaitQueue { next_ticket: 0, waiting: VecDeque::new() }
+    }
+
+    fn enqueue(&mut self) -> u64 {
+        self.next_ticket += 1;
+        self.waiting.push_back(self.next_ticket);
+        self.next_ticket
+    }
+
+    /// 1-based place of `ticket` in the queue.
+    fn position(&self, ticket: u64) -> usize {
+        self.waiting.iter().position(|&waiting| waiting == ticket).map_or(0, |index| index + 1)
+    }
+
+    fn leave(&mut self, ticket: u64) {
+        self.waiting.retain(|&waiting| waiting != ticket);
+        QUEUE_CHANGED.notify_all();
+    }
+}
+
+/// Takes a client slot if one is free. Call with the queue locked.
+fn take_slot(_queue: &MutexGuard<WaitQueue>) -> bool {
+    let taken = ACTIVE_CLIENTS.fetch_update(Ordering::SeqCst, Ordering::SeqCst, |active| {
+        (active < unsafe { MAX_CLIENTS }).then_some(active + 1)
+    });
+    taken.is_ok()
+}
+
+fn release_slot() {
+    let _queue = QUEUE.lock().unwrap();
+    ACTIVE_CLIENTS.fetch_sub(1, Ordering::SeqCst);
+    QUEUE_CHANGED.notify_all();
+}
+
+/// Holds `stream`, already queued under `ticket`, until it reaches the front
+/// and a slot frees up, telling the client its position as it moves. Returns
+/// `false` if the client gave up or waited too long.
+fn wait_for_slot(stream: &TcpStream, peer: SocketAddr, ticket: u64) -> io::Result<bool> {
+    let deadline = Instant::now() + Duration::from_secs(unsafe { QUEUE_TIMEOUT });
+    let mut queue = QUEUE.lock().unwrap();
+    let mut last_update: Option<(usize, Instant)> = None;
+
+    loop {
+        let position = queue.position(ticket);
+        if position == 1 && take_slot(&queue) {
+            queue.leave(ticket);
+            debug_println!("Admitting {} from the queue.", peer);
+            return Ok(true);
+        }
+
+        let now = Instant::now();
+        if now >= deadline {
+            queue.leave(ticket);
+            let queued = queue.waiting.len();
+            drop(queue);
+            let hint = BUSY.lock().unwrap().reject(unsafe { MAX_CLIENTS }, queued);
+            println!("Client {} waited too long in the queue. Rejecting.", peer);
+            send_error(&mut BufWriter::new(stream), GetError::ServerBusy(Some(hint)))?;
+            return Ok(false);
+        }
+
+        let due = last_update.is_none_or(|(told, at)| told != position || now >= at + QUEUE_UPDATE);
+        if due {
+            drop(queue);
+            let sent = Response::Queued(position).encode(&mut BufWriter::new(stream));
+            queue = QUEUE.lock().unwrap();
+            if sent.is_err() {
+                debug_println!("Client {} left the queue.", peer);
+                queue.leave(ticket);
+                return Ok(false);
+            }
+            last_update = Some((position, now));
+            continue;
+        }
+
+        let next_update = last_update.map_or(now, |(_, at)| at + QUEUE_UPDATE);
+        let wake = next_update.min(deadline);
+        queue = QUEUE_CHANGED.wait_timeout(queue, wake.saturating_duration_since(now)).unwrap().0;
+    }
+}
+
+/// Estimates when turned-away clients will find a free slot, from how long
+/// clients stay connected and how many were already told to come back.
+struct BusyTracker {
+    /// Moving average of how long clients stay connected.
+    average_session: Option<Duration>,
+    /// When each client turned away was told to come back.
+    promised: Vec<Instant>,
+}
+
+impl BusyTracker {
+    const fn new() -> BusyTracker {
+        BusyTracker { average_session: None, promised: Vec::new() }
+    }
+
+    fn record_session(&mut self, length: Duration) {
+        self.average_session = Some(match self.average_session {
+            Some(average) => (average * 3 + length) / 4,
+            None => length,
+        });
+    }
+
+    /// Hint for a client turned away now. Clients already waiting, `queued`
+    /// of them in the queue, are ahead of it, so it is told to come back
+    /// after them.
+    fn reject(&mut self, max_clients: usize, queued: usize) -> BusyHint {
+        let now = Instant::now();
+        self.promised.retain(|&deadline| deadline > now);
+        let position = queued + self.promised.len() + 1;
+
+        let session = self.average_session.unwrap_or(DEFAULT_SESSION);
+        let wait = session.as_secs_f64() * position as f64 / max_clients.max(1) as f64;
+        let retry_after = (wait.ceil() as u64).clamp(1, MAX_RETRY_AFTER);
+        self.promised.push(now + Duration::from_secs(retry_after));
+        BusyHint { retry_after, position }
+    }
+}
+
+fn root_dir() -> &'static Path {
+    ROOT_DIR.get().expect("root directory is set before accepting clients")
+}
+
+fn downloads() -> &'static Scheduler {
+    DOWNLOADS.get().expect("download limiter is set before accepting clients")
+}
+
+fn uploads() -> &'static Scheduler {
+    UPLOADS.get().expect("upload limiter is set before accepting clients")
+}
+
+/// Which clients a rule applies to: everyone connecting from an address, or
+/// every address on its own. The user in `HELLO` is only the client's word, so
+/// no rule goes by it.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+enum ClientMatch {
+    Ip(IpAddr),
+    EachIp,
+}
+
+impl ClientMatch {
+    fn matches(&self, ip: IpAddr) -> bool {
+        match self {
+            ClientMatch::Ip(addr) => *addr == ip,
+            ClientMatch::EachIp => true,
+        }
+    }
+
+    /// Whether the rule counts the clients of `group`, an address.
+    fn covers(&self, group: &ClientMatch) -> bool {
+        match (self, group) {
+            (ClientMatch::EachIp, ClientMatch::Ip(_)) => true,
+            (rule, group) => rule == group,
+        }
+    }
+
+    /// The address whose clients this rule counts together.
+    fn group(&self, ip: IpAddr) -> ClientMatch {
+        match self {
+            ClientMatch::EachIp => ClientMatch::Ip(ip),
+            client => client.clone(),
+        }
+    }
+}
+
+impl std::fmt::Display for ClientMatch {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ClientMatch::Ip(ip) => write!(f, "from {}", ip),
+            ClientMatch::EachIp => write!(f, "from any one address"),
+        }
+    }
+}
+
+/// Parses `<ip|*>=<value>`, where `*` stands for each address on its own.
+/// Anything else, networks and host names included, is not a rule.
+fn parse_rule<T: FromStr>(rule: &str) -> Option<(ClientMatch, T)> {
+    let (client, value) = rule.rsplit_once('=')?;
+    let client = match client {
+        "*" => ClientMatch::EachIp,
+        _ => ClientMatch::Ip(client.parse().ok()?),
+    };
+    Some((client, value.parse().ok()?))
+}
+
+/// A connection counted against `--max-connections` until it is dropped.
+struct Claim(ClientMatch);
+
+impl Drop for Claim {
+    fn drop(&mut self) {
+        let mut open = CONNECTIONS.lock().unwrap();
+        if let Some(count) = open.get_mut(&self.0) {
+            *count -= 1;
+            if *count == 0 {
+                open.remove(&self.0);
+            }
+        }
+    }
+}
+
+/// Counts one more connection for `group`, an address, unless a rule
+/// covering it is already at its limit. The error says which limit.
+fn claim_connection(group: ClientMatch) -> Result<Claim, String> {
+    let rules = CONNECTION_LIMITS.get().map_or(&[][..], Vec::as_slice);
+    let mut open = CONNECTIONS.lock().unwrap();
+    let count = open.get(&group).copied().unwrap_or(0);
+    let full = rules.iter().find(|(rule, max)| rule.covers(&group) && count >= *max);
+    if let Some((_, max)) = full {
+        return Err(format!("{} connection(s) {}", max, group));
+    }
+    *open.entry(group.clone()).or_insert(0) += 1;
+    Ok(Claim(group))
+}
+
+/// A client's place in the bandwidth schedulers, shared by all its transfers.
+struct ClientShare {
+    /// The client's flow, the same for every connection from its address,
+    /// so that opening more connections does not buy more turns.
+    flow: Arc<u64>,
+    weight: u32,
+    /// Schedulers for the `--max-rate` rules covering the client.
+    caps: Vec<Arc<Scheduler>>,
+    /// Transfers the client has running.
+    transfers: AtomicUsize,
+}
+
+impl ClientShare {
+    /// The first `--weight` rule matching the address sets the weight; the
+    /// default is 1.
+    fn new(ip: IpAddr) -> ClientShare {
+        let rules = WEIGHTS.get().map_or(&[][..], Vec::as_slice);
+        let weight = rules.iter().find(|(client, _)| client.matches(ip)).map_or(1, |&(_, weight)| weight);
+
+        let rules = RATE_LIMITS.get().map_or(&[][..], Vec::as_slice);
+        let mut caps = CAPS.lock().unwrap();
+        // Nobody is left under these
+        caps.retain(|_, cap| Arc::strong_count(cap) > 1);
+        let caps = rules
+            .iter()
+            .filter(|(client, _)| client.matches(ip))
+            .map(|(client, rate)| {
+                let cap = caps.entry(client.group(ip)).or_insert_with(|| Arc::new(Scheduler::new(*rate, unsafe { BURST } as u64)));
+                Arc::clone(cap)
+            })
+            .collect();
+
+        let mut flows = FLOWS.lock().unwrap();
+        flows.retain(|_, flow| Arc::strong_count(flow) > 1);
+        let flow = flows.entry(ClientMatch::Ip(ip)).or_insert_with(|| Arc::new(NEXT_FLOW.fetch_add(1, Ordering::SeqCst)));
+
+        ClientShare { flow: Arc::clone(flow), weight, caps, transfers: AtomicUsize::new(0) }
+    }
+}
+
+/// A timeout of `secs` seconds, where 0 means waiting forever.
+fn timeout(secs: u64) -> Option<Duration> {
+    (secs > 0).then(|| Duration::from_secs(secs))
+}
+
+/// Sockets report an expired read timeout as `WouldBlock` on some platforms.
+fn is_timeout(err: &io::Error) -> bool {
+    matches!(err.kind(), io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut)
+}
+
+/// Readers whose timeout changes between waiting for the next request, which
+/// may take up to the idle timeout, and waiting for the next chunk of an upload.
+trait ReadTimeout {
+    fn set_read_timeout(&mut self, timeout: Option<Duration>) -> io::Result<()>;
+}
+
+impl ReadTimeout for BufReader<&TcpStream> {
+    fn set_read_timeout(&mut self, timeout: Option<Duration>) -> io::Result<()> {
+        self.get_ref().set_read_timeout(timeout)
+    }
+}
+
+impl ReadTimeout for BufReader<StreamReader> {
+    fn set_read_timeout(&mut self, timeout: Option<Duration>) -> io::Result<()> {
+        self.get_mut().set_read_timeout(timeout);
+        Ok(())
+    }
+}
+
+/// Root-relative form of `path` for messages sent to the client.
+fn client_path(path: &Path) -> String {
+    path.strip_prefix(root_dir()).unwrap_or(path).to_string_lossy().into_owned()
+}
 
 fn send_error<W: Write>(writer: &mut W, err: GetError) -> io::Result<()> {
     debug_eprintln!("Sending error to client: {}", err);
-    writeln!(writer, "ERR {}", err)?;
-    writer.flush()?;
-    Ok(())
+    Response::Err(err).encode(writer)
 }
 
-fn rate_limit(bytes_read: usize) {
+/// An even split of `rate` between clients and their transfers, used as
+/// the chunk size so that a turn in the scheduler stays short.
+fn per_transfer_rate(rate: usize, share: &ClientShare) -> Option<usize> {
     let active = ACTIVE_CLIENTS.load(Ordering::SeqCst);
-    if active > 0 {
-        let per_client_rate = std::cmp::max(1, unsafe { TRANSFER_RATE } / active);
-        let delay_ms = (bytes_read * 1000) / per_client_rate;
-        thread::sleep(Duration::from_millis(delay_ms as u64));
+    let per_client_rate = rate.checked_div(active)?;
+    Some(per_client_rate / std::cmp::max(1, share.transfers.load(Ordering::SeqCst)))
+}
+
+/// Waits for the client's turn to move `bytes` more in the direction
+/// `limiter` paces, before they are sent or asked for. The scheduler splits
+/// the rate between clients by weight and keeps the total at the rate.
+/// Any `--max-rate` caps on the client are waited on first, so that the
+/// chunk does not hold a turn in `limiter` while its cap keeps it back.
+fn rate_limit(limiter: &Scheduler, share: &ClientShare, bytes: usize) {
+    if let Some(ready) = share.caps.iter().map(|cap| cap.reserve(*share.flow, share.weight, bytes)).max() {
+        thread::sleep(ready.saturating_duration_since(Instant::now()));
     }
+    let ready = limiter.reserve(*share.flow, share.weight, bytes);
+    thread::sleep(ready.saturating_duration_since(Instant::now()));
 }
 
-fn calculate_chunk_size() -> usize {
-    let active = ACTIVE_CLIENTS.load(Ordering::SeqCst);
-    if active == 0 {
-        return unsafe { TRANSFER_RATE };
+fn calculate_chunk_size(limiter: &Scheduler, share: &ClientShare) -> usize {
+    let rate = limiter.rate() as usize;
+    let chunk_size = if rate == 0 {
+        UNLIMITED_CHUNK_SIZE
+    } else {
+        match per_transfer_rate(rate, share) {
+            Some(rate) => std::cmp::max(1, rate),
+            None => rate,
+        }
+    };
+    // A capped client's transfers split its cap instead
+    let transfers = std::cmp::max(1, share.transfers.load(Ordering::SeqCst));
+    share.caps.iter().map(|cap| std::cmp::max(1, cap.rate() as usize / transfers)).fold(chunk_size, usize::min)
+}
+
+fn describe_rate(rate: u64) -> String {
+    if rate == 0 {
+        "unlimited".to_string()
+    } else {
+        format!("{} B/s", rate)
     }
-    let per_client_rate = std::cmp::max(1, unsafe { TRANSFER_RATE } / active);
-    per_client_rate
 }
 
-fn handle_get(
-    reader: &mut BufReader<&TcpStream>,
-    writer: &mut BufWriter<&TcpStream>,
-    remote_path: &std::path::Path,
-    offset: usize,
-) -> io::Result<()> {
-    let _ = reader;
+/// Prints how much each direction moved over every `interval`, against its
+/// limit, whenever anything moved at all.
+fn report_stats(interval: Duration) {
+    let (mut last_down, mut last_up) = (0, 0);
+    loop {
+        thread::sleep(interval);
+        let (down, up) = (DOWNLOADED.load(Ordering::Relaxed), UPLOADED.load(Ordering::Relaxed));
+        if down == last_down && up == last_up {
+            continue;
+        }
+        let seconds = interval.as_secs_f64();
+        println!(
+            "Stats: {} active client(s); downloads {} bytes ({:.0} B/s, limit {}); uploads {} bytes ({:.0} B/s, limit {})",
+            ACTIVE_CLIENTS.load(Ordering::SeqCst),
+            down,
+            (down - last_down) as f64 / seconds,
+            describe_rate(downloads().rate()),
+            up,
+            (up - last_up) as f64 / seconds,
+            describe_rate(uploads().rate()),
+        );
+        (last_down, last_up) = (down, up);
+    }
+}
 
+fn handle_get<W: Write>(
+    writer: &mut W,
+    remote_path: &Path,
+    offset: u64,
+    checksums: bool,
+    share: &ClientShare,
+) -> io::Result<()> {
     debug_println!("Handling GET request: path='{}', offset={}", remote_path.display(), offset);
 
-    let mut file = match File::open(&remote_path) {
+    let mut file = match File::open(remote_path) {
         Ok(f) => f,
         Err(e) => {
             debug_eprintln!("Failed to open file '{}': {}", remote_path.display(), e);
-            send_error(writer, GetError::FileError(e.to_string()))?;
+            send_error(writer, GetError::from_fs(&e, &client_path(remote_path)))?;
             return Ok(());
         }
     };
 
-    let filesize = file.metadata()?.len() as usize;
+    let filesize = file.metadata()?.len();
     if offset >= filesize {
         debug_println!("Offset >= filesize. Sending 'OK 0'.");
-        writeln!(writer, "OK 0")?;
-        writer.flush()?;
-        return Ok(());
     }
+    let start = std::cmp::min(offset, filesize);
 
-    file.seek(SeekFrom::Start(offset as u64))?;
-    let remaining = filesize - offset;
-    writeln!(writer, "OK {}", remaining)?;
-    writer.flush()?;
+    // The final digest covers the whole file, so the client can tell whether
+    // the bytes it already had before resuming belong to the same file.
+    let mut digest = Sha256::new();
+    if checksums {
+        digest.update_from_reader(&mut file, start)?;
+    } else {
+        file.seek(SeekFrom::Start(start))?;
+    }
+
+    let remaining = (filesize - start) as usize;
+    Response::Ok(Some(remaining as u64)).encode(writer)?;
     debug_println!("Sent 'OK {}' to client for GET.", remaining);
 
     let mut total_sent = 0;
     while total_sent < remaining {
-        let chunk_size = calculate_chunk_size();
-        writeln!(writer, "NEXT {}", chunk_size)?;
-        writer.flush()?;
-        debug_println!("GET: Sent 'NEXT {}' to client.", chunk_size);
-
+        let chunk_size = calculate_chunk_size(downloads(), share);
         let to_read = std::cmp::min(chunk_size, remaining - total_sent);
-        let mut buffer = vec![0u8; to_read];
-        let bytes_read = file.read(&mut buffer)?;
+        let mut buffer = Vec::with_capacity(to_read);
+        let bytes_read = (&mut file).take(to_read as u64).read_to_end(&mut buffer)?;
 
         if bytes_read == 0 {
             debug_println!("File ended unexpectedly during GET. total_sent={} remaining={}.", total_sent, remaining);
             break;
         }
 
-        writer.write_all(&buffer[..bytes_read])?;
+        rate_limit(downloads(), share, bytes_read);
+        let crc = if checksums {
+            digest.update(&buffer);
+            Some(crc32c(&buffer))
+        } else {
+            None
+        };
+        Response::Next { size: bytes_read, crc }.encode(writer)?;
+        debug_println!("GET: Sent 'NEXT {}' to client.", bytes_read);
+
+        writer.write_all(&buffer)?;
         writer.flush()?;
         total_sent += bytes_read;
+        DOWNLOADED.fetch_add(bytes_read as u64, Ordering::Relaxed);
         debug_println!("GET: Sent {} bytes. Total sent: {} / {}", bytes_read, total_sent, remaining);
+    }
 
-        rate_limit(bytes_read);
+    if checksums && total_sent == remaining {
+        Response::Done { digest: digest.finish_hex() }.encode(writer)?;
+        debug_println!("GET: Sent file digest to client.");
     }
 
     debug_println!("File transfer complete for GET request.");
     Ok(())
 }
 
-fn handle_put(
-    reader: &mut BufReader<&TcpStream>,
-    writer: &mut BufWriter<&TcpStream>,
-    remote_path: &std::path::Path,
+/// Reads one `DATA` frame of at most `max_size` bytes and checks its CRC.
+/// The outer error is I/O; the inner one is what to report to the client.
+fn receive_checked_chunk<R: BufRead>(
+    reader: &mut R,
+    max_size: usize,
     offset: usize,
-    total_size: usize,
+) -> io::Result<Result<Vec<u8>, GetError>> {
+    let (size, crc) = match Request::decode(reader) {
+        Ok(Request::Data { size, crc }) if size <= max_size => (size, crc),
+        Ok(_) => return Ok(Err(GetError::InvalidCommand)),
+        Err(e) => match GetError::from_io(&e) {
+            Some(err) => return Ok(Err(err.clone())),
+            None => return Err(e),
+        },
+    };
+
+    let mut buffer = vec![0u8; size];
+    reader.read_exact(&mut buffer)?;
+    if crc32c(&buffer) != crc {
+        return Ok(Err(GetError::IntegrityError(format!("chunk at offset {}", offset))));
+    }
+    Ok(Ok(buffer))
+}
+
+fn file_digest(path: &Path, len: u64) -> io::Result<String> {
+    let mut digest = Sha256::new();
+    digest.update_from_reader(&mut File::open(path)?, len)?;
+    Ok(digest.finish_hex())
+}
+
+/// How much of an upload of `total_size` bytes is already on disk and can be
+/// resumed. Only a checksummed upload can trust an existing prefix, since its
+/// final digest exposes one that belongs to a different file.
+fn resumable_offset(file: &File, total_size: u64, checksums: bool) -> io::Result<u64> {
+    let have = file.metadata()?.len();
+    if checksums && have <= total_size {
+        Ok(have)
+    } else {
+        Ok(0)
+    }
+}
+
+/// Hidden file next to `path` that receives an upload until it is complete,
+/// so readers never see a half-written destination.
+fn partial_path(path: &Path) -> Option<PathBuf> {
+    let name = path.file_name()?;
+    let mut partial = OsString::from(".");
+    partial.push(name);
+    partial.push(PARTIAL_SUFFIX);
+    Some(path.with_file_name(partial))
+}
+
+/// A destination, and so its partial file, held by one upload until dropped.
+struct UploadClaim(PathBuf);
+
+impl Drop for UploadClaim {
+    fn drop(&mut self) {
+        ACTIVE_UPLOADS.lock().unwrap().remove(&self.0);
+    }
+}
+
+/// Claims `destination` for an upload, unless another one is writing it.
+fn claim_upload(destination: &Path) -> Option<UploadClaim> {
+    let claimed = ACTIVE_UPLOADS.lock().unwrap().insert(destination.to_path_buf());
+    claimed.then(|| UploadClaim(destination.to_path_buf()))
+}
+
+fn is_partial(path: &Path) -> bool {
+    path.file_name()
+        .is_some_and(|name| os_str_bytes(name).ends_with(PARTIAL_SUFFIX.as_bytes()))
+}
+
+/// Flushes a finished upload to disk and moves it over the destination.
+fn commit_upload(file: File, partial: &Path, destination: &Path) -> io::Result<()> {
+    file.sync_all()?;
+    drop(file);
+    rename(partial, destination)?;
+    // Persist the rename itself; not every platform can open a directory
+    if let Some(parent) = destination.parent() {
+        let _ = File::open(parent).and_then(|dir| dir.sync_all());
+    }
+    Ok(())
+}
+
+/// Removes partial uploads under `dir` that have not been written to for
+/// longer than `max_age`. Returns how many were removed.
+fn collect_stale_partials(dir: &Path, max_age: Duration) -> usize {
+    let Ok(entries) = read_dir(dir) else {
+        return 0;
+    };
+
+    let mut removed = 0;
+    for entry in entries.flatten() {
+        let path = entry.path();
+        let Ok(metadata) = entry.metadata() else {
+            continue;
+        };
+
+        if metadata.is_dir() {
+            removed += collect_stale_partials(&path, max_age);
+        } else if metadata.is_file() && is_partial(&path) {
+            let age = metadata.modified().ok().and_then(|modified| modified.elapsed().ok());
+            if age.is_some_and(|age| age > max_age) && remove_file(&path).is_ok() {
+                debug_println!("Removed stale partial upload '{}'", path.display());
+                removed += 1;
+            }
+        }
+    }
+    removed
+}
+
+fn handle_put<R: BufRead, W: Write>(
+    reader: &mut R,
+    writer: &mut W,
+    remote_path: &Path,
+    total_size: u64,
+    checksums: bool,
+    share: &ClientShare,
 ) -> io::Result<()> {
     debug_println!(
-        "Handling PUT request: path='{}', offset={}, total_size={}",
+        "Handling PUT request: path='{}', total_size={}",
         remote_path.display(),
-        offset,
         total_size
     );
 
+    let Some(partial) = partial_path(remote_path) else {
+        send_error(writer, GetError::FileError("Invalid destination".to_string()))?;
+        return Ok(());
+    };
+    let Some(_claim) = claim_upload(remote_path) else {
+        eprintln!("Refusing a second upload to '{}' while one is running.", remote_path.display());
+        let busy = format!("'{}' is already being uploaded", client_path(remote_path));
+        send_error(writer, GetError::FileError(busy))?;
+        return Ok(());
+    };
+
     if let Some(parent) = remote_path.parent() {
         if !parent.exists() {
             debug_println!("Creating directory '{}'", parent.display());
@@ -117,29 +676,49 @@ fn handle_put(
         }
     }
 
-    let mut file = match OpenOptions::new().write(true).create(true).open(&remote_path) {
+    let mut file = match OpenOptions::new().write(true).create(true).truncate(false).open(&partial) {
         Ok(f) => f,
         Err(e) => {
-            debug_eprintln!("Failed to open file '{}': {}", remote_path.display(), e);
-            send_error(writer, GetError::FileError(e.to_string()))?;
+            debug_eprintln!("Failed to open file '{}': {}", partial.display(), e);
+            send_error(writer, GetError::from_fs(&e, &client_path(remote_path)))?;
             return Ok(());
         }
     };
 
-    file.seek(SeekFrom::Start(offset as u64))?;
-    writeln!(writer, "OK")?;
-    writer.flush()?;
-    debug_println!("Acknowledged PUT request. Ready to receive data.");
+    // The server decides where the upload resumes; the client only follows
+    let offset = resumable_offset(&file, total_size, checksums)?;
+    file.set_len(offset)?;
+    file.seek(SeekFrom::Start(offset))?;
+    Response::Ok(Some(offset)).encode(writer)?;
+    debug_println!("Acknowledged PUT request with 'OK {}'. Ready to receive data.", offset);
 
-    let mut received = offset;
+    let (total_size, mut received) = (total_size as usize, offset as usize);
     while received < total_size {
-        let chunk_size = calculate_chunk_size();
-        writeln!(writer, "NEXT {}", chunk_size)?;
-        writer.flush()?;
+        let chunk_size = calculate_chunk_size(uploads(), share);
+        rate_limit(uploads(), share, std::cmp::min(chunk_size, total_size - received));
+        Response::Next { size: chunk_size, crc: None }.encode(writer)?;
         debug_println!("PUT: Sent 'NEXT {}' to client.", chunk_size);
 
-        let mut buffer = vec![0u8; chunk_size];
-        let bytes_read = reader.read(&mut buffer)?;
+        let buffer = if checksums {
+            let max_size = std::cmp::min(chunk_size, total_size - received);
+            match receive_checked_chunk(reader, max_size, received) {
+                Ok(Ok(buffer)) => buffer,
+                Ok(Err(err)) => {
+                    eprintln!("Rejected chunk for '{}': {}", remote_path.display(), err);
+                    send_error(writer, err)?;
+                    return Ok(());
+                }
+                Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => Vec::new(),
+                Err(e) => return Err(e),
+            }
+        } else {
+            let mut buffer = vec![0u8; chunk_size];
+            let bytes_read = reader.read(&mut buffer)?;
+            buffer.truncate(bytes_read);
+            buffer
+        };
+
+        let bytes_read = buffer.len();
         if bytes_read == 0 {
             eprintln!(
                 "Client closed connection prematurely. Received {} out of {} bytes.",
@@ -152,81 +731,518 @@ fn handle_put(
         file.write_all(&buffer[..bytes_to_write])?;
         file.flush()?;
         received += bytes_to_write;
+        UPLOADED.fetch_add(bytes_to_write as u64, Ordering::Relaxed);
 
         debug_println!("PUT: Received {} bytes. Total received: {} / {}", bytes_to_write, received, total_size);
-
-        rate_limit(bytes_read);
     }
 
-    if received == total_size {
-        println!("File upload complete for '{}'.", remote_path.display());
-    } else {
+    if received != total_size {
+        // The partial file stays behind so the client can resume later
         eprintln!(
             "Upload incomplete for '{}'. Received {} out of {} bytes.",
             remote_path.display(),
             received,
             total_size
         );
+        return Ok(());
     }
 
+    if checksums {
+        let expected = match Request::decode(reader) {
+            Ok(Request::Done { digest }) => digest,
+            Ok(_) => {
+                send_error(writer, GetError::InvalidCommand)?;
+                return Ok(());
+            }
+            Err(e) => {
+                eprintln!("Client did not send a digest for '{}': {}", remote_path.display(), e);
+                return Ok(());
+            }
+        };
+
+        if file_digest(&partial, total_size as u64)? != expected {
+            eprintln!("Digest mismatch for '{}'. Discarding upload.", remote_path.display());
+            drop(file);
+            remove_file(&partial)?;
+            send_error(writer, GetError::IntegrityError("file digest mismatch".to_string()))?;
+            return Ok(());
+        }
+    }
+
+    if let Err(e) = commit_upload(file, &partial, remote_path) {
+        eprintln!("Failed to move upload into '{}': {}", remote_path.display(), e);
+        if checksums {
+            send_error(writer, GetError::FileError(e.to_string()))?;
+        }
+        return Ok(());
+    }
+    if checksums {
+        Response::Ok(None).encode(writer)?;
+    }
+
+    println!("File upload complete for '{}'.", remote_path.display());
+    Ok(())
+}
+
+fn handle_stat<W: Write>(writer: &mut W, remote_path: &Path) -> io::Result<()> {
+    debug_println!("Handling STAT request: path='{}'", remote_path.display());
+
+    match std::fs::metadata(remote_path) {
+        Ok(metadata) => Response::Stat(FileStat::from_metadata(&metadata)).encode(writer),
+        Err(e) => {
+            debug_eprintln!("Failed to stat '{}': {}", remote_path.display(), e);
+            send_error(writer, GetError::from_fs(&e, &client_path(remote_path)))
+        }
+    }
+}
+
+/// Streams an `ENTRY` for every match under `dir`, depth first in name
+/// order. Without `recursive`, only descends as deep as the pattern has
+/// slashes. Symlinks are reported but never followed, so recursion stays
+/// inside the served root.
+fn list_entries<W: Write>(
+    writer: &mut W,
+    dir: &Path,
+    prefix: &OsStr,
+    depth: usize,
+    recursive: bool,
+    pattern: Option<&[u8]>,
+    count: &mut u64,
+) -> io::Result<()> {
+    let mut entries: Vec<_> = match read_dir(dir) {
+        Ok(entries) => entries.flatten().collect(),
+        Err(e) => {
+            debug_eprintln!("Skipping unreadable directory '{}': {}", dir.display(), e);
+            return Ok(());
+        }
+    };
+    entries.sort_by_key(|entry| entry.file_name());
+
+    for entry in entries {
+        let path = entry.path();
+        if is_partial(&path) {
+            continue;
+        }
+        let Ok(metadata) = entry.metadata() else {
+            continue;
+        };
+
+        let name = entry.file_name();
+        let mut relative = prefix.to_os_string();
+        if !relative.is_empty() {
+            relative.push("/");
+        }
+        relative.push(&name);
+
+        // Patterns with a slash match the relative path, others just the name
+        let matched = match pattern {
+            Some(pattern) if pattern.contains(&b'/') => glob_match(pattern, &os_str_bytes(&relative)),
+            Some(pattern) => glob_match(pattern, &os_str_bytes(&name)),
+            None => true,
+        };
+        if matched {
+            Response::Entry { path: relative.clone(), stat: FileStat::from_metadata(&metadata) }.encode(writer)?;
+            *count += 1;
+        }
+
+        let pattern_depth = pattern.map_or(0, |pattern| pattern.iter().filter(|&&b| b == b'/').count());
+        if metadata.is_dir() && (recursive || depth < pattern_depth) {
+            list_entries(writer, &path, &relative, depth + 1, recursive, pattern, count)?;
+        }
+    }
     Ok(())
 }
 
+fn handle_list<W: Write>(
+    writer: &mut W,
+    remote_path: &Path,
+    recursive: bool,
+    pattern: Option<&OsStr>,
+) -> io::Result<()> {
+    debug_println!("Handling LIST request: path='{}', recursive={}", remote_path.display(), recursive);
+
+    let metadata = match std::fs::metadata(remote_path) {
+        Ok(metadata) => metadata,
+        Err(e) => {
+            debug_eprintln!("Failed to list '{}': {}", remote_path.display(), e);
+            return send_error(writer, GetError::from_fs(&e, &client_path(remote_path)));
+        }
+    };
+
+    // Listing a file describes just that file, like `ls` does
+    if !metadata.is_dir() {
+        let name = remote_path.file_name().unwrap_or_default().to_os_string();
+        Response::Entry { path: name, stat: FileStat::from_metadata(&metadata) }.encode(writer)?;
+        return Response::Ok(Some(1)).encode(writer);
+    }
+
+    let pattern = pattern.map(os_str_bytes);
+    let mut count = 0;
+    list_entries(writer, remote_path, OsStr::new(""), 0, recursive, pattern.as_deref(), &mut count)?;
+    Response::Ok(Some(count)).encode(writer)
+}
+
+/// Answers a file management request with `OK` or the error it ran into.
+fn reply_fs_result<W: Write>(writer: &mut W, path: &Path, result: io::Result<()>) -> io::Result<()> {
+    match result {
+        Ok(()) => Response::Ok(None).encode(writer),
+        Err(e) => {
+            debug_eprintln!("File operation on '{}' failed: {}", path.display(), e);
+            send_error(writer, GetError::from_fs(&e, &client_path(path)))
+        }
+    }
+}
+
+/// Like `resolve_request_path`, but also refuses the served root itself,
+/// which must never be deleted or renamed.
+fn resolve_mutable_path<W: Write>(
+    writer: &mut W,
+    requested: &OsStr,
+    peer: SocketAddr,
+) -> io::Result<Option<PathBuf>> {
+    match resolve_request_path(writer, requested, peer)? {
+        Some(path) if path == root_dir() => {
+            eprintln!("Denied modifying the served root for {}", peer);
+            send_error(writer, GetError::AccessDenied(requested.to_string_lossy().into_owned()))?;
+            Ok(None)
+        }
+        resolved => Ok(resolved),
+    }
+}
+
+/// Maps a client path into the served root, answering `Access denied`
+/// and returning `None` when it would escape.
+fn resolve_request_path<W: Write>(
+    writer: &mut W,
+    requested: &OsStr,
+    peer: SocketAddr,
+) -> io::Result<Option<PathBuf>> {
+    match resolve_in_root(root_dir(), requested) {
+        // Partial uploads belong to the server until they are renamed
+        Ok(path) if is_partial(&path) => {
+            eprintln!("Denied access to partial upload '{}' for {}", requested.to_string_lossy(), peer);
+            send_error(writer, GetError::AccessDenied(requested.to_string_lossy().into_owned()))?;
+            Ok(None)
+        }
+        Ok(path) => Ok(Some(path)),
+        Err(err) => {
+            eprintln!("Denied access to '{}' for {}", requested.to_string_lossy(), peer);
+            send_error(writer, err)?;
+            Ok(None)
+        }
+    }
+}
+
+fn read_request<R: BufRead, W: Write>(
+    reader: &mut R,
+    writer: &mut W,
+    peer: SocketAddr,
+) -> io::Result<Option<Request>> {
+    match Request::decode(reader) {
+        Ok(request) => {
+            debug_println!("Command received from {}: {}", peer, request);
+            Ok(Some(request))
+        }
+        // The client hung up between requests
+        Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => Ok(None),
+        Err(e) if is_timeout(&e) => {
+            println!("Closing idle connection from {}.", peer);
+            Ok(None)
+        }
+        Err(e) => {
+            debug_eprintln!("Invalid command from {}: {}", peer, e);
+            let err = GetError::from_io(&e).cloned().unwrap_or(GetError::InvalidCommand);
+            send_error(writer, err)?;
+            Ok(None)
+        }
+    }
+}
+
+/// Expects `HELLO` as the first request and answers with the capabilities
+/// both sides support. Returns `None` once the peer has been told why the
+/// connection cannot continue.
+fn handshake(
+    reader: &mut BufReader<&TcpStream>,
+    writer: &mut BufWriter<&TcpStream>,
+    peer: SocketAddr,
+) -> io::Result<Option<Vec<String>>> {
+    let (version, capabilities) = match read_request(reader, writer, peer)? {
+        Some(Request::Hello { version, capabilities, user }) => {
+            if let Some(user) = &user {
+                debug_println!("Client {} identifies as user '{}'", peer, user);
+            }
+            (version, capabilities)
+        }
+        Some(_) => {
+            // Clients predating the handshake open with GET/PUT directly
+            eprintln!("Client {} did not send HELLO. Rejecting legacy client.", peer);
+            send_error(writer, GetError::VersionMismatch { client: 0, server: PROTOCOL_VERSION })?;
+            return Ok(None);
+        }
+        None => return Ok(None),
+    };
+
+    if version != PROTOCOL_VERSION {
+        eprintln!("Client {} speaks protocol version {}, expected {}.", peer, version, PROTOCOL_VERSION);
+        send_error(writer, GetError::VersionMismatch { client: version, server: PROTOCOL_VERSION })?;
+        return Ok(None);
+    }
+
+    let negotiated = negotiate_capabilities(CAPABILITIES, &capabilities);
+    Response::Hello { version: PROTOCOL_VERSION, capabilities: negotiated.clone() }.encode(writer)?;
+    debug_println!("Handshake with {} complete. Capabilities: {:?}", peer, negotiated);
+    Ok(Some(negotiated))
+}
+
 fn handle_client(stream: TcpStream) -> io::Result<()> {
     let peer = stream.peer_addr()?;
     debug_println!("New connection from {}", peer);
 
+    // A stalled or silent peer would otherwise hold a client slot forever
+    stream.set_read_timeout(timeout(unsafe { CONNECT_TIMEOUT }))?;
+    stream.set_write_timeout(timeout(unsafe { CHUNK_TIMEOUT }))?;
+
     let mut reader = BufReader::new(&stream);
     let mut writer = BufWriter::new(&stream);
 
-    let mut command = String::new();
-    if reader.read_line(&mut command)? == 0 {
-        debug_eprintln!("No command received from {}", peer);
-        send_error(&mut writer, GetError::InvalidCommand)?;
-        return Ok(());
+    let capabilities = match handshake(&mut reader, &mut writer, peer)? {
+        Some(capabilities) => capabilities,
+        None => return Ok(()),
+    };
+    stream.set_read_timeout(timeout(unsafe { IDLE_TIMEOUT }))?;
+    let share = ClientShare::new(peer.ip());
+    if share.weight != 1 {
+        debug_println!("Client {} has bandwidth weight {}", peer, share.weight);
     }
 
-    let command = command.trim_end().to_string();
-    debug_println!("Command received from {}: {}", peer, command);
+    if has_capability(&capabilities, CAP_MUX) {
+        drop(writer);
+        let link = Arc::new(Mutex::new(BufWriter::new(stream.try_clone()?)));
+        serve_streams(&mut reader, link, peer, &capabilities, &share)?;
+    } else {
+        let session = has_capability(&capabilities, CAP_SESSION);
+        serve_requests(&mut reader, &mut writer, peer, &capabilities, session, &share)?;
+    }
 
-    let parts: Vec<&str> = command.split_whitespace().collect();
-    if parts.is_empty() {
-        debug_eprintln!("Empty command from {}", peer);
-        send_error(&mut writer, GetError::InvalidCommand)?;
-        return Ok(());
+    debug_println!("Finished handling client {}", peer);
+    Ok(())
+}
+
+/// One of the `MAX_STREAMS` of a connection, given back when the client
+/// closes the stream or when its thread ends, whichever comes first.
+struct StreamSlot<'a> {
+    running: &'a AtomicUsize,
+    released: AtomicBool,
+}
+
+impl StreamSlot<'_> {
+    fn release(&self) {
+        if !self.released.swap(true, Ordering::SeqCst) {
+            self.running.fetch_sub(1, Ordering::SeqCst);
+        }
     }
+}
 
-    let cmd = parts[0].to_uppercase();
-    if cmd == "GET" {
-        if parts.len() < 3 {
-            debug_eprintln!("GET command missing arguments from {}", peer);
-            send_error(&mut writer, GetError::MissingArguments)?;
-            return Ok(());
+/// Serves a multiplexed connection. Every stream is a session of its own,
+/// run on its own thread, and all of them split the connection's share.
+fn serve_streams(
+    reader: &mut BufReader<&TcpStream>,
+    link: Arc<Mutex<BufWriter<TcpStream>>>,
+    peer: SocketAddr,
+    capabilities: &[String],
+    share: &ClientShare,
+) -> io::Result<()> {
+    let demux = Demux::new();
+    // Streams the client has open whose thread is still running. A stream
+    // the client closes stops counting at once; its thread, told so by its
+    // writer, stops at its next write
+    let running = AtomicUsize::new(0);
+    let mut slots: HashMap<u32, Arc<StreamSlot>> = HashMap::new();
+
+    thread::scope(|scope| {
+        loop {
+            let (stream, payload) = match read_frame(reader) {
+                Ok(Some(frame)) => frame,
+                Ok(None) => break,
+                // Streams waiting on a download have nothing to send meanwhile
+                Err(e) if is_timeout(&e) => {
+                    if share.transfers.load(Ordering::SeqCst) > 0 {
+                        continue;
+                    }
+                    println!("Closing idle connection from {}.", peer);
+                    break;
+                }
+                Err(e) => {
+                    eprintln!("Invalid frame from {}: {}", peer, e);
+                    break;
+                }
+            };
+
+            if !payload.is_empty() && !demux.is_open(stream) && running.load(Ordering::SeqCst) >= MAX_STREAMS {
+                eprintln!("Client {} opened more than {} streams. Rejecting stream {}.", peer, MAX_STREAMS, stream);
+                let limit = format!("{} streams per connection", MAX_STREAMS);
+                send_error(&mut StreamWriter::new(stream, Arc::clone(&link)), GetError::LimitReached(limit))?;
+                continue;
+            }
+            if !payload.is_empty() && !demux.is_open(stream) {
+                debug_println!("Opening stream {} for {}", stream, peer);
+                let mut frames = demux.open(stream);
+                frames.set_read_timeout(timeout(unsafe { IDLE_TIMEOUT }));
+                let mut writer = StreamWriter::new(stream, Arc::clone(&link));
+                writer.abort_when_closed(frames.closed());
+                let mut reader = BufReader::new(frames);
+                let link = Arc::clone(&link);
+
+                running.fetch_add(1, Ordering::SeqCst);
+                slots.retain(|_, slot| !slot.released.load(Ordering::SeqCst));
+                let slot = Arc::new(StreamSlot { running: &running, released: AtomicBool::new(false) });
+                slots.insert(stream, Arc::clone(&slot));
+                scope.spawn(move || {
+                    let result = serve_requests(&mut reader, &mut writer, peer, capabilities, true, share);
+                    drop((reader, writer));
+                    slot.release();
+                    debug_println!("Closed stream {} for {}", stream, peer);
+                    // A write that timed out may have left half a frame on the
+                    // wire, and a stalled stream most likely means a stalled peer
+                    if result.is_err_and(|e| is_timeout(&e)) {
+                        let _ = link.lock().unwrap().get_ref().shutdown(Shutdown::Both);
+                    }
+                });
+            }
+            if payload.is_empty() {
+                if let Some(slot) = slots.remove(&stream) {
+                    slot.release();
+                }
+            }
+            demux.deliver(stream, payload);
         }
-        let remote_path = normalize_path(parts[1]);
-        let offset: usize = parts[2].parse().unwrap_or(0);
-        handle_get(&mut reader, &mut writer, &remote_path, offset)?;
-    } else if cmd == "PUT" {
-        if parts.len() < 4 {
-            debug_eprintln!("PUT command missing arguments from {}", peer);
-            send_error(&mut writer, GetError::MissingArguments)?;
-            return Ok(());
+        // Lets the stream threads see EOF so the scope can finish
+        demux.close_all();
+        Ok(())
+    })
+}
+
+/// Answers requests until the client quits or hangs up. Metadata and file
+/// management requests may always come first; without a session, a GET or
+/// PUT is the last request on the connection.
+fn serve_requests<R: BufRead + ReadTimeout, W: Write>(
+    reader: &mut R,
+    writer: &mut W,
+    peer: SocketAddr,
+    capabilities: &[String],
+    session: bool,
+    share: &ClientShare,
+) -> io::Result<()> {
+    let checksums = has_capability(capabilities, CAP_CHECKSUMS);
+
+    while let Some(request) = read_request(reader, writer, peer)? {
+        match request {
+            Request::Stat { path } => {
+                if let Some(remote_path) = resolve_request_path(writer, &path, peer)? {
+                    handle_stat(writer, &remote_path)?;
+                }
+            }
+            Request::List { path, recursive, pattern } => {
+                if let Some(remote_path) = resolve_request_path(writer, &path, peer)? {
+                    handle_list(writer, &remote_path, recursive, pattern.as_deref())?;
+                }
+            }
+            Request::Delete { path } => {
+                if let Some(target) = resolve_mutable_path(writer, &path, peer)? {
+                    let result = remove_file(&target);
+                    reply_fs_result(writer, &target, result)?;
+                }
+            }
+            Request::Rmdir { path } => {
+                if let Some(target) = resolve_mutable_path(writer, &path, peer)? {
+                    let result = remove_dir(&target);
+                    reply_fs_result(writer, &target, result)?;
+                }
+            }
+            Request::Mkdir { path } => {
+                if let Some(target) = resolve_mutable_path(writer, &path, peer)? {
+                    let result = create_dir_all(&target);
+                    reply_fs_result(writer, &target, result)?;
+                }
+            }
+            Request::Rename { from, to } => {
+                let Some(source) = resolve_mutable_path(writer, &from, peer)? else {
+                    continue;
+                };
+                if let Some(destination) = resolve_mutable_path(writer, &to, peer)? {
+                    let result = rename(&source, &destination);
+                    // A missing source is the likelier culprit than a missing target directory
+                    let culprit = if source.symlink_metadata().is_ok() { &destination } else { &source };
+                    reply_fs_result(writer, culprit, result)?;
+                }
+            }
+            Request::Get { path, offset } => {
+                if let Some(remote_path) = resolve_request_path(writer, &path, peer)? {
+                    share.transfers.fetch_add(1, Ordering::SeqCst);
+                    let result = handle_get(writer, &remote_path, offset, checksums, share);
+                    share.transfers.fetch_sub(1, Ordering::SeqCst);
+                    result?;
+                }
+                if !session {
+                    break;
+                }
+            }
+            Request::Put { path, total_size } => {
+                if let Some(remote_path) = resolve_request_path(writer, &path, peer)? {
+                    share.transfers.fetch_add(1, Ordering::SeqCst);
+                    reader.set_read_timeout(timeout(unsafe { CHUNK_TIMEOUT }))?;
+                    let result = handle_put(reader, writer, &remote_path, total_size, checksums, share);
+                    share.transfers.fetch_sub(1, Ordering::SeqCst);
+                    if result.as_ref().is_err_and(is_timeout) {
+                        println!("Upload from {} stalled. Closing connection.", peer);
+                    }
+                    result?;
+                    reader.set_read_timeout(timeout(unsafe { IDLE_TIMEOUT }))?;
+                }
+                if !session {
+                    break;
+                }
+            }
+            Request::Quit => {
+                Response::Ok(None).encode(writer)?;
+                break;
+            }
+            Request::Hello { .. } | Request::Data { .. } | Request::Done { .. } => {
+                debug_eprintln!("Out-of-sequence command from {}", peer);
+                send_error(writer, GetError::InvalidCommand)?;
+                break;
+            }
         }
-        let remote_path = normalize_path(parts[1]);
-        let offset: usize = parts[2].parse().unwrap_or(0);
-        let total_size: usize = parts[3].parse().unwrap_or(0);
-        handle_put(&mut reader, &mut writer, &remote_path, offset, total_size)?;
-    } else {
-        debug_eprintln!("Unknown command '{}' from {}", cmd, peer);
-        send_error(&mut writer, GetError::UnknownCommand)?;
     }
-
-    debug_println!("Finished handling client {}", peer);
     Ok(())
 }
 
+/// The value after the flag at `args[*i]`, parsed as `T`, and `*i` moved
+/// onto it. Exits with an error when the value is missing or does not parse.
+fn parse_flag_value<T: FromStr>(args: &[String], i: &mut usize, name: &str) -> T {
+    flag_value(args, i, name, |value| value.parse().ok())
+}
+
+/// Like `parse_flag_value`, with `parse` deciding which values are valid.
+fn flag_value<T>(args: &[String], i: &mut usize, name: &str, parse: impl Fn(&str) -> Option<T>) -> T {
+    *i += 1;
+    let Some(value) = args.get(*i) else {
+        eprintln!("Error: Missing value for {}", name);
+        process::exit(1);
+    };
+    parse(value).unwrap_or_else(|| {
+        eprintln!("Error: Invalid value for {}", name);
+        process::exit(1);
+    })
+}
+
 fn main() -> io::Result<()> {
     let args: Vec<String> = env::args().collect();
+    let mut root = env::current_dir()?;
+    let mut listen: Vec<SocketAddr> = Vec::new();
+    let mut weights = Vec::new();
+    let mut connection_limits = Vec::new();
+    let mut rate_limits = Vec::new();
     let mut i = 1;
     while i < args.len() {
         match args[i].as_str() {
@@ -234,40 +1250,35 @@ fn main() -> io::Result<()> {
                 unsafe { shared_lib::debug_utils::DEBUG_MODE = true };
                 println!("Debug mode enabled.");
             }
-            "--max-clients" => {
-                if i + 1 < args.len() {
-                    unsafe {
-                        MAX_CLIENTS = match args[i + 1].parse() {
-                            Ok(val) => val,
-                            Err(_) => {
-                                eprintln!("Error: Invalid value for --max-clients");
-                                process::exit(1);
-                            }
-                        };
-                    }
-                    i += 1;
-                } else {
-                    eprintln!("Error: Missing value for --max-clients");
-                    process::exit(1);
-                }
+            "--max-clients" => unsafe { MAX_CLIENTS = parse_flag_value(&args, &mut i, "--max-clients") },
+            "--transfer-rate" => unsafe {
+                DOWNLOAD_RATE = parse_flag_value(&args, &mut i, "--transfer-rate");
+                UPLOAD_RATE = DOWNLOAD_RATE;
             }
-            "--transfer-rate" => {
-                if i + 1 < args.len() {
-                    unsafe {
-                        TRANSFER_RATE = match args[i + 1].parse() {
-                            Ok(val) => val,
-                            Err(_) => {
-                                eprintln!("Error: Invalid value for --transfer-rate");
-                                process::exit(1);
-                            }
-                        };
-                    }
-                    i += 1;
-                } else {
-                    eprintln!("Error: Missing value for --transfer-rate");
-                    process::exit(1);
-                }
+            "--download-rate" => unsafe { DOWNLOAD_RATE = parse_flag_value(&args, &mut i, "--download-rate") },
+            "--upload-rate" => unsafe { UPLOAD_RATE = parse_flag_value(&args, &mut i, "--upload-rate") },
+            "--burst" => unsafe { BURST = parse_flag_value(&args, &mut i, "--burst") },
+            "--weight" => {
+                let valid = |rule: &str| parse_rule::<u32>(rule).filter(|&(_, weight)| weight > 0);
+                weights.push(flag_value(&args, &mut i, "--weight, expected <ip|*>=<weight>", valid));
             }
+            "--max-connections" => {
+                let rule = flag_value(&args, &mut i, "--max-connections, expected <ip|*>=<count>", parse_rule);
+                connection_limits.push(rule);
+            }
+            "--max-rate" => {
+                let valid = |rule: &str| parse_rule::<u64>(rule).filter(|&(_, rate)| rate > 0);
+                rate_limits.push(flag_value(&args, &mut i, "--max-rate, expected <ip|*>=<rate>", valid));
+            }
+            "--idle-timeout" => unsafe { IDLE_TIMEOUT = parse_flag_value(&args, &mut i, "--idle-timeout") },
+            "--connect-timeout" => unsafe { CONNECT_TIMEOUT = parse_flag_value(&args, &mut i, "--connect-timeout") },
+            "--chunk-timeout" => unsafe { CHUNK_TIMEOUT = parse_flag_value(&args, &mut i, "--chunk-timeout") },
+            "--queue-size" => unsafe { QUEUE_SIZE = parse_flag_value(&args, &mut i, "--queue-size") },
+            "--queue-timeout" => unsafe { QUEUE_TIMEOUT = parse_flag_value(&args, &mut i, "--queue-timeout") },
+            "--stats-interval" => unsafe { STATS_INTERVAL = parse_flag_value(&args, &mut i, "--stats-interval") },
+            "--partial-max-age" => unsafe { PARTIAL_MAX_AGE = parse_flag_value(&args, &mut i, "--partial-max-age") },
+            "--listen" => listen.extend(flag_value(&args, &mut i, "--listen", |value| value.to_socket_addrs().ok())),
+            "--root" => root = flag_value(&args, &mut i, "--root", |value| Some(PathBuf::from(value))),
             _ => {
                 eprintln!("Error: Unknown argument '{}'", args[i]);
                 process::exit(1);
@@ -276,35 +1287,139 @@ fn main() -> io::Result<()> {
         i += 1;
     }
     
-    let listener = TcpListener::bind("127.0.0.1:7878")?;
-    debug_println!("Server running on port 7878");
+    let root = match root.canonicalize() {
+        Ok(root) if root.is_dir() => root,
+        _ => {
+            eprintln!("Error: Invalid value for --root: '{}' is not a directory", root.display());
+            process::exit(1);
+        }
+    };
+    println!("Serving files from '{}'", root.display());
+    ROOT_DIR.set(root).expect("root directory is only set once");
+
+    let (download_rate, upload_rate, burst) = unsafe { (DOWNLOAD_RATE, UPLOAD_RATE, BURST) };
+    DOWNLOADS.set(Scheduler::new(download_rate as u64, burst as u64)).expect("download limiter is only set once");
+    UPLOADS.set(Scheduler::new(upload_rate as u64, burst as u64)).expect("upload limiter is only set once");
+    WEIGHTS.set(weights).expect("weights are only set once");
+    CONNECTION_LIMITS.set(connection_limits).expect("connection limits are only set once");
+    RATE_LIMITS.set(rate_limits).expect("rate limits are only set once");
+
+    let partial_max_age = unsafe { PARTIAL_MAX_AGE };
+    if partial_max_age > 0 {
+        let max_age = Duration::from_secs(partial_max_age);
+        let interval = std::cmp::min(max_age, Duration::from_secs(60 * 60));
+        thread::spawn(move || loop {
+            let removed = collect_stale_partials(root_dir(), max_age);
+            if removed > 0 {
+                println!("Removed {} stale partial upload(s).", removed);
+            }
+            thread::sleep(interval);
+        });
+    }
+
+    let stats_interval = unsafe { STATS_INTERVAL };
+    if stats_interval > 0 {
+        thread::spawn(move || report_stats(Duration::from_secs(stats_interval)));
+    }
+
+    if listen.is_empty() {
+        listen.extend(DEFAULT_LISTEN.to_socket_addrs()?);
+    }
+    let mut listeners = Vec::new();
+    for addr in listen {
+        match TcpListener::bind(addr) {
+            Ok(listener) => {
+                let local = listener.local_addr()?;
+                println!("Listening on {}", local);
+                debug_println!("Server running on port {}", local.port());
+                listeners.push(listener);
+            }
+            Err(e) => {
+                eprintln!("Error: Cannot listen on {}: {}", addr, e);
+                process::exit(1);
+            }
+        }
+    }
+
+    let acceptors: Vec<_> = listeners
+        .into_iter()
+        .map(|listener| thread::spawn(move || accept_clients(listener)))
+        .collect();
+    for acceptor in acceptors {
+        acceptor.join().expect("accept thread panicked")?;
+    }
+    Ok(())
+}
 
+fn accept_clients(listener: TcpListener) -> io::Result<()> {
     for stream in listener.incoming() {
         let stream = stream?;
-        let current_clients = ACTIVE_CLIENTS.load(Ordering::SeqCst);
+        let Ok(peer) = stream.peer_addr() else {
+            continue;
+        };
+        // One address may not take every slot, or every place in the queue
+        let claim = match claim_connection(ClientMatch::Ip(peer.ip())) {
+            Ok(claim) => claim,
+            Err(limit) => {
+                eprintln!("Client {} is over its limit of {}. Rejecting new connection.", peer, limit);
+                // A peer gone before the reply must not stop the listener
+                if let Err(e) = send_error(&mut BufWriter::new(&stream), GetError::LimitReached(limit)) {
+                    eprintln!("Failed to reject client {}: {}", peer, e);
+                }
+                continue;
+            }
+        };
+        let mut queue = QUEUE.lock().unwrap();
 
-        if current_clients >= unsafe { MAX_CLIENTS } {
-            eprintln!("Maximum clients reached. Rejecting new connection.");
-            let mut writer = BufWriter::new(&stream);
-            send_error(&mut writer, GetError::ServerBusy)?;
+        if queue.waiting.is_empty() && take_slot(&queue) {
+            drop(queue);
+            thread::spawn(move || serve_client(stream, claim));
             continue;
         }
 
-        ACTIVE_CLIENTS.fetch_add(1, Ordering::SeqCst);
-        println!(
-            "Client connected. Active clients: {}",
-            ACTIVE_CLIENTS.load(Ordering::SeqCst)
-        );
+        // Queued under the same lock as the check, so a burst of connections
+        // cannot all fit into the last place
+        if queue.waiting.len() < unsafe { QUEUE_SIZE } {
+            let ticket = queue.enqueue();
+            drop(queue);
+            println!("Maximum clients reached. Queueing new connection.");
+            thread::spawn(move || {
+                if let Ok(true) = wait_for_slot(&stream, peer, ticket) {
+                    serve_client(stream, claim);
+                }
+            });
+            continue;
+        }
+        let queued = queue.waiting.len();
+        drop(queue);
 
-        thread::spawn(move || {
-            let _ = handle_client(stream);
-            ACTIVE_CLIENTS.fetch_sub(1, Ordering::SeqCst);
-            println!(
-                "Client disconnected. Active clients: {}",
-                ACTIVE_CLIENTS.load(Ordering::SeqCst)
-            );
-        });
+        let hint = BUSY.lock().unwrap().reject(unsafe { MAX_CLIENTS }, queued);
+        eprintln!(
+            "Maximum clients reached. Rejecting new connection (queue position {}, retry after {}s).",
+            hint.position, hint.retry_after
+        );
+        let mut writer = BufWriter::new(&stream);
+        if let Err(e) = send_error(&mut writer, GetError::ServerBusy(Some(hint))) {
+            eprintln!("Failed to reject client {}: {}", peer, e);
+        }
     }
 
     Ok(())
-}
\ No newline at end of file
+}
+
+/// Serves a client that already holds a slot, and frees the slot after.
+/// The client's address stays counted until then.
+fn serve_client(stream: TcpStream, _claim: Claim) {
+    println!(
+        "Client connected. Active clients: {}",
+        ACTIVE_CLIENTS.load(Ordering::SeqCst)
+    );
+    let connected = Instant::now();
+    let _ = handle_client(stream);
+    BUSY.lock().unwrap().record_session(connected.elapsed());
+    release_slot();
+    println!(
+        "Client disconnected. Active clients: {}",
+        ACTIVE_CLIENTS.load(Ordering::SeqCst)
+    );
+}
@@ -0,0 +1,36 @@
+use std::io::{BufRead, BufReader};
+use std::net::TcpStream;
+use std::process::{Command, Stdio};
+use std::thread;
+use std::time::Duration;
+
+fn busy_reply() -> String {
+    let stream = TcpStream::connect("127.0.0.1:7982").expect("Failed to connect");
+    let mut reply = String::new();
+    BufReader::new(stream).read_line(&mut reply).expect("Failed to read reply");
+    reply.trim_end().to_string()
+}
+
+#[test]
+fn test_busy_reply_carries_hint() {
+    let mut server = Command::new("../target/debug/remcp-serv")
+        .args(["--max-clients", "0", "--listen", "127.0.0.1:7982"])
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .spawn()
+        .expect("Failed to start server");
+    thread::sleep(Duration::from_secs(2));
+
+    // Without any measured sessions the server assumes 5 seconds per client,
+    // and each client turned away queues behind the ones before it
+    let first = busy_reply();
+    let second = busy_reply();
+    let third = busy_reply();
+
+    server.kill().ok();
+    server.wait().ok();
+
+    assert_eq!(first, "ERR Server is busy (retry after 5s, queue position 1)");
+    assert_eq!(second, "ERR Server is busy (retry after 10s, queue position 2)");
+    assert_eq!(third, "ERR Server is busy (retry after 15s, queue position 3)");
+}
@@ -0,0 +1,64 @@
+//! Helpers shared by the server integration tests. Each test file uses only
+//! some of them.
+#![allow(dead_code)]
+
+use std::io::{BufRead, BufReader, Write};
+use std::net::{TcpStream, ToSocketAddrs};
+use std::process::{Child, Command, Stdio};
+use std::thread;
+use std::time::Duration;
+
+/// A connection that speaks the protocol by hand, one line at a time.
+pub struct Client {
+    pub stream: TcpStream,
+    pub reader: BufReader<TcpStream>,
+}
+
+impl Client {
+    /// Connects to `addr`. Reads give up after 10 seconds.
+    pub fn connect<A: ToSocketAddrs>(addr: A) -> Client {
+        let stream = TcpStream::connect(addr).expect("Failed to connect");
+        stream.set_read_timeout(Some(Duration::from_secs(10))).unwrap();
+        let reader = BufReader::new(stream.try_clone().unwrap());
+        Client { stream, reader }
+    }
+
+    pub fn send(&mut self, line: &str) {
+        self.stream.write_all(format!("{}\n", line).as_bytes()).expect("Failed to send request");
+    }
+
+    /// The next reply, or an empty string once the server closed the connection.
+    pub fn reply(&mut self) -> String {
+        let mut reply = String::new();
+        self.reader.read_line(&mut reply).expect("Failed to read reply");
+        reply.trim_end().to_string()
+    }
+
+    /// Sends `line` and returns the reply to it.
+    pub fn request(&mut self, line: &str) -> String {
+        self.send(line);
+        self.reply()
+    }
+
+    /// The next reply other than a reminder of the place already reported.
+    pub fn reply_after(&mut self, reminder: &str) -> String {
+        loop {
+            let reply = self.reply();
+            if reply != reminder {
+                return reply;
+            }
+        }
+    }
+}
+
+/// Starts a server with `args`, its output discarded, and gives it time to listen.
+pub fn start_server(args: &[&str]) -> Child {
+    let server = Command::new("../target/debug/remcp-serv")
+        .args(args)
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .spawn()
+        .expect("Failed to start server");
+    thread::sleep(Duration::from_secs(2));
+    server
+}
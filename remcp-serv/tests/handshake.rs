@@ -0,0 +1,49 @@
+use std::fs::{create_dir_all, remove_dir_all, write};
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::TcpStream;
+use std::process::{Command, Stdio};
+use std::thread;
+use std::time::Duration;
+
+/// Sends `line` as the first request of a new connection and returns every
+/// line the server answers with before it closes the connection.
+fn open_with(line: &str) -> Vec<String> {
+    let mut stream = TcpStream::connect("127.0.0.1:8004").expect("Failed to connect");
+    stream.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+    stream.write_all(format!("{}\n", line).as_bytes()).expect("Failed to send request");
+    let mut replies = String::new();
+    BufReader::new(stream).read_to_string(&mut replies).expect("Connection was not closed");
+    replies.lines().map(str::to_string).collect()
+}
+
+#[test]
+fn test_version_mismatch() {
+    let root = "handshake_test_root";
+    create_dir_all(root).expect("Failed to create test root");
+    write(format!("{}/file.txt", root), b"never sent").expect("Failed to write test file");
+
+    let mut server = Command::new("../target/debug/remcp-serv")
+        .args(["--root", root, "--listen", "127.0.0.1:8004"])
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .spawn()
+        .expect("Failed to start server");
+    thread::sleep(Duration::from_secs(2));
+
+    let newer = open_with("HELLO 2 checksums session");
+    // Clients predating the handshake open with the request itself
+    let legacy = open_with("GET file.txt 0");
+
+    let mut current = TcpStream::connect("127.0.0.1:8004").expect("Failed to connect");
+    current.write_all(b"HELLO 1 checksums\n").expect("Failed to send request");
+    let mut hello = String::new();
+    BufReader::new(&current).read_line(&mut hello).expect("Failed to read reply");
+
+    server.kill().ok();
+    server.wait().ok();
+    remove_dir_all(root).ok();
+
+    assert_eq!(newer, ["ERR Protocol version mismatch (client 2, server 1)"]);
+    assert_eq!(legacy, ["ERR Protocol version mismatch (client 0, server 1)"]);
+    assert_eq!(hello.trim_end(), "HELLO 1 checksums", "A matching version should be accepted");
+}
@@ -0,0 +1,107 @@
+use std::fs::{create_dir_all, read, remove_dir_all, write};
+use std::process::{Command, Stdio};
+use std::thread;
+use std::time::{Duration, Instant};
+
+mod common;
+use common::{start_server, Client};
+
+#[test]
+fn test_connection_limits() {
+    let mut server = start_server(&[
+        "--listen", "127.0.0.1:7992", "--listen", "[::1]:7992",
+        "--max-clients", "10", "--max-connections", "*=2", "--max-connections", "::1=1",
+    ]);
+
+    let mut first = Client::connect("127.0.0.1:7992");
+    let first_hello = first.request("HELLO 1 session");
+    let mut second = Client::connect("127.0.0.1:7992");
+    let second_hello = second.request("HELLO 1 session");
+    thread::sleep(Duration::from_millis(200));
+
+    // Turned away on connect, before it says anything
+    let mut third = Client::connect("127.0.0.1:7992");
+    let third_reply = third.reply();
+    let refused = Command::new("../target/debug/remcp")
+        .args(["--retries", "0", "127.0.0.1:7992:file.txt", "limits_test_unused.txt"])
+        .output()
+        .expect("Failed to run client");
+
+    // Another address is counted on its own, under the tighter of its rules
+    let mut v6_first = Client::connect("[::1]:7992");
+    let v6_first_hello = v6_first.request("HELLO 1 session");
+    thread::sleep(Duration::from_millis(200));
+    let mut v6_second = Client::connect("[::1]:7992");
+    let v6_second_reply = v6_second.reply();
+
+    // Leaving frees the place for the next connection
+    first.request("QUIT");
+    thread::sleep(Duration::from_millis(200));
+    let mut fourth = Client::connect("127.0.0.1:7992");
+    let fourth_hello = fourth.request("HELLO 1 session");
+
+    server.kill().ok();
+    server.wait().ok();
+
+    assert_eq!(first_hello, "HELLO 1 session");
+    assert_eq!(second_hello, "HELLO 1 session");
+    assert_eq!(third_reply, "ERR Client limit reached: 2 connection(s) from 127.0.0.1");
+    assert_eq!(refused.status.code(), Some(4), "A client over its limit should exit as busy");
+    assert!(String::from_utf8_lossy(&refused.stderr).contains("Client limit reached: 2 connection(s) from 127.0.0.1"));
+    assert_eq!(v6_first_hello, "HELLO 1 session");
+    assert_eq!(v6_second_reply, "ERR Client limit reached: 1 connection(s) from ::1");
+    assert_eq!(fourth_hello, "HELLO 1 session");
+}
+
+#[test]
+fn test_invalid_rules() {
+    for (option, rule) in [
+        ("--max-connections", "10.0.0.300=2"),
+        ("--max-rate", "10.0.0.0/8=1000"),
+        ("--weight", "alice=2"),
+        ("--max-rate", "=1000"),
+    ] {
+        let output = Command::new("../target/debug/remcp-serv")
+            .args(["--listen", "127.0.0.1:7999", option, rule])
+            .output()
+            .expect("Failed to start server");
+        assert_eq!(output.status.code(), Some(1), "{} {} should be refused", option, rule);
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        assert!(stderr.contains(&format!("Error: Invalid value for {}", option)), "Unexpected error: {}", stderr);
+    }
+}
+
+#[test]
+fn test_rate_limits() {
+    let root = "limits_test_root";
+    let work_dir = "limits_test_downloads";
+    create_dir_all(root).expect("Failed to create test root");
+    create_dir_all(work_dir).expect("Failed to create work directory");
+    let content: Vec<u8> = (0..20_000).map(|i| (i % 239) as u8).collect();
+    write(format!("{}/capped.bin", root), &content).expect("Failed to write test file");
+
+    // The server as a whole is unlimited, but not this address
+    let mut server = start_server(&[
+        "--root", root, "--listen", "127.0.0.1:7996", "--transfer-rate", "0", "--max-rate", "127.0.0.1=10000",
+    ]);
+    let local = format!("{}/capped.bin", work_dir);
+    let start = Instant::now();
+    let status = Command::new("../target/debug/remcp")
+        .args(["127.0.0.1:7996:capped.bin", &local])
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .status()
+        .expect("Failed to run client");
+    let elapsed = start.elapsed();
+    let downloaded = read(&local).unwrap_or_default();
+
+    server.kill().ok();
+    server.wait().ok();
+    remove_dir_all(root).ok();
+    remove_dir_all(work_dir).ok();
+
+    assert!(status.success(), "Capped download failed");
+    assert!(downloaded == content, "Downloaded content differs");
+    assert!(elapsed >= Duration::from_millis(1800), "Faster than the cap allows: {:?}", elapsed);
+    assert!(elapsed < Duration::from_secs(4), "Slower than the cap allows: {:?}", elapsed);
+}
@@ -0,0 +1,196 @@
+use std::collections::HashMap;
+use std::fs::{create_dir_all, remove_dir_all, write};
+use std::io::{BufRead, BufReader, Cursor, Read, Write};
+use std::net::TcpStream;
+use std::process::{Command, Stdio};
+use std::thread;
+use std::time::{Duration, Instant};
+use shared_lib::mux::{read_frame, write_frame};
+use shared_lib::Response;
+
+#[test]
+fn test_streams_share_one_connection() {
+    let root = "multiplex_test_root";
+    create_dir_all(root).expect("Failed to create test root");
+    let first: Vec<u8> = (0..200u32).map(|i| (i % 251) as u8).collect();
+    let second = vec![b'x'; 200];
+    write(format!("{}/first.bin", root), &first).expect("Failed to write test file");
+    write(format!("{}/second.bin", root), &second).expect("Failed to write test file");
+
+    let mut server = Command::new("../target/debug/remcp-serv")
+        .arg("--root")
+        .arg(root)
+        .arg("--transfer-rate")
+        .arg("100")
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .expect("Failed to start server");
+
+    if let Some(stdout) = server.stdout.take() {
+        thread::spawn(move || {
+            for line in BufReader::new(stdout).lines().map_while(Result::ok) {
+                println!("[SERVER STDOUT] {}", line);
+            }
+        });
+    }
+
+    thread::sleep(Duration::from_secs(2));
+
+    let mut stream = TcpStream::connect("127.0.0.1:7878").expect("Failed to connect");
+    let mut reader = BufReader::new(stream.try_clone().unwrap());
+    stream.write_all(b"HELLO 1 mux\n").unwrap();
+    let mut hello = String::new();
+    reader.read_line(&mut hello).unwrap();
+
+    write_frame(&mut stream, 1, b"GET first.bin 0\n").unwrap();
+    write_frame(&mut stream, 2, b"GET second.bin 0\n").unwrap();
+
+    // Both downloads run at once, so their frames arrive interleaved
+    let mut order = Vec::new();
+    let mut received: HashMap<u32, Vec<u8>> = HashMap::new();
+    let (mut open, mut closing) = (2, false);
+    while open > 0 {
+        let Some((id, payload)) = read_frame(&mut reader).expect("Failed to read frame") else {
+            break;
+        };
+        if payload.is_empty() {
+            open -= 1;
+        } else if order.last() != Some(&id) {
+            order.push(id);
+        }
+        received.entry(id).or_default().extend(payload);
+        if !closing && received.len() == 2 && received.values().all(|bytes| bytes.len() > 200) {
+            // Both transfers are done; close the streams
+            closing = true;
+            write_frame(&mut stream, 1, b"").unwrap();
+            write_frame(&mut stream, 2, b"").unwrap();
+        }
+    }
+    drop(stream);
+
+    server.kill().ok();
+    server.wait().ok();
+    remove_dir_all(root).ok();
+
+    assert_eq!(hello.trim(), "HELLO 1 mux");
+    assert!(order.len() > 2, "Streams did not interleave: {:?}", order);
+
+    for (id, expected) in [(1, &first), (2, &second)] {
+        let mut wire = Cursor::new(received.remove(&id).unwrap_or_default());
+        assert_eq!(Response::decode(&mut wire).unwrap(), Response::Ok(Some(200)));
+        let (mut data, mut sizes) = (Vec::new(), Vec::new());
+        while data.len() < 200 {
+            match Response::decode(&mut wire).unwrap() {
+                Response::Next { size, .. } => {
+                    sizes.push(size);
+                    let mut chunk = vec![0u8; size];
+                    wire.read_exact(&mut chunk).unwrap();
+                    data.extend(chunk);
+                }
+                other => panic!("Unexpected response on stream {}: {}", id, other),
+            }
+        }
+        assert!(&data == expected, "Content mismatch on stream {}", id);
+        // While both run, they split the connection's 100 bytes/s
+        assert!(sizes.iter().filter(|&&size| size == 50).count() >= 2, "Stream {} chunks: {:?}", id, sizes);
+    }
+}
+
+#[test]
+fn test_stream_limit() {
+    let mut server = Command::new("../target/debug/remcp-serv")
+        .args(["--listen", "127.0.0.1:8001"])
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .spawn()
+        .expect("Failed to start server");
+    thread::sleep(Duration::from_secs(2));
+
+    let mut stream = TcpStream::connect("127.0.0.1:8001").expect("Failed to connect");
+    stream.set_read_timeout(Some(Duration::from_secs(10))).unwrap();
+    let mut reader = BufReader::new(stream.try_clone().unwrap());
+    stream.write_all(b"HELLO 1 mux\n").unwrap();
+    let mut hello = String::new();
+    reader.read_line(&mut hello).unwrap();
+
+    // Sends a `STAT` on stream `id` and returns the first reply to it
+    let mut reply_on = |stream: &mut TcpStream, id: u32| {
+        write_frame(stream, id, b"STAT .\n").unwrap();
+        loop {
+            let (from, payload) = read_frame(&mut reader).expect("Failed to read frame").expect("Connection closed");
+            if from == id && !payload.is_empty() {
+                return String::from_utf8_lossy(&payload).trim_end().to_string();
+            }
+        }
+    };
+    let open: Vec<String> = (1..=4).map(|id| reply_on(&mut stream, id)).collect();
+    let rejected = reply_on(&mut stream, 5);
+
+    // Closing a stream makes room for another
+    write_frame(&mut stream, 1, b"").unwrap();
+    thread::sleep(Duration::from_millis(200));
+    let reopened = reply_on(&mut stream, 6);
+
+    server.kill().ok();
+    server.wait().ok();
+
+    assert_eq!(hello.trim(), "HELLO 1 mux");
+    for reply in open.iter().chain([&reopened]) {
+        assert!(reply.starts_with("STAT "), "Unexpected reply: {}", reply);
+    }
+    assert_eq!(rejected, "ERR Client limit reached: 4 streams per connection");
+}
+
+#[test]
+fn test_abandoned_stream_is_stopped() {
+    let root = "abandoned_stream_root";
+    create_dir_all(root).expect("Failed to create test root");
+    write(format!("{}/big.bin", root), vec![b'x'; 100_000]).expect("Failed to write test file");
+
+    let mut server = Command::new("../target/debug/remcp-serv")
+        .args(["--root", root, "--listen", "127.0.0.1:8008", "--transfer-rate", "400"])
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .spawn()
+        .expect("Failed to start server");
+    thread::sleep(Duration::from_secs(2));
+
+    let mut stream = TcpStream::connect("127.0.0.1:8008").expect("Failed to connect");
+    stream.set_read_timeout(Some(Duration::from_secs(10))).unwrap();
+    let mut reader = BufReader::new(stream.try_clone().unwrap());
+    stream.write_all(b"HELLO 1 mux\n").unwrap();
+    let mut hello = String::new();
+    reader.read_line(&mut hello).unwrap();
+
+    // Every stream is taken by a download that will not finish during the test
+    for id in 1..=4 {
+        write_frame(&mut stream, id, b"GET big.bin 0\n").unwrap();
+    }
+    while read_frame(&mut reader).expect("Failed to read frame").expect("Connection closed").0 != 1 {}
+
+    // Give up on stream 1 and take its place right away
+    write_frame(&mut stream, 1, b"").unwrap();
+    write_frame(&mut stream, 5, b"STAT big.bin\n").unwrap();
+    let (mut sent_after_close, mut stopped, mut stat) = (0, false, None);
+    let deadline = Instant::now() + Duration::from_secs(5);
+    while (!stopped || stat.is_none()) && Instant::now() < deadline {
+        let (from, payload) = read_frame(&mut reader).expect("Failed to read frame").expect("Connection closed");
+        match from {
+            1 if payload.is_empty() => stopped = true,
+            1 => sent_after_close += 1,
+            5 if stat.is_none() => stat = Some(String::from_utf8_lossy(&payload).trim_end().to_string()),
+            _ => {}
+        }
+    }
+
+    server.kill().ok();
+    server.wait().ok();
+    remove_dir_all(root).ok();
+
+    assert_eq!(hello.trim(), "HELLO 1 mux");
+    assert!(stat.as_deref().is_some_and(|reply| reply.starts_with("STAT ")), "Unexpected reply: {:?}", stat);
+    assert!(stopped, "The server kept the abandoned stream open");
+    // At most the chunks already under way when the stream was closed
+    assert!(sent_after_close <= 2, "{} frames were sent on the abandoned stream", sent_after_close);
+}
@@ -0,0 +1,112 @@
+use std::fs::{File, create_dir_all, read, remove_dir_all, write};
+use std::io::{BufRead, BufReader, Write};
+use std::net::TcpStream;
+use std::path::Path;
+use std::process::{Command, Stdio};
+use std::thread;
+use std::time::{Duration, SystemTime};
+
+#[test]
+fn test_stale_partials_are_collected() {
+    let root = Path::new("partials_test_root");
+    create_dir_all(root.join("nested")).expect("Failed to create test root");
+
+    let stale = root.join("nested/.old.bin.remcp-part");
+    let fresh = root.join(".new.bin.remcp-part");
+    let regular = root.join("old.bin");
+    for path in [&stale, &fresh, &regular] {
+        write(path, b"partial").expect("Failed to write test file");
+    }
+
+    let two_hours_ago = SystemTime::now() - Duration::from_secs(2 * 60 * 60);
+    for path in [&stale, &regular] {
+        File::options()
+            .write(true)
+            .open(path)
+            .and_then(|f| f.set_modified(two_hours_ago))
+            .expect("Failed to age test file");
+    }
+
+    let mut server = Command::new("../target/debug/remcp-serv")
+        .arg("--root")
+        .arg(root)
+        .arg("--partial-max-age")
+        .arg("3600")
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .expect("Failed to start server");
+
+    if let Some(stdout) = server.stdout.take() {
+        let stdout_reader = BufReader::new(stdout);
+        thread::spawn(move || {
+            for line in stdout_reader.lines().map_while(Result::ok) {
+                println!("[SERVER STDOUT] {}", line);
+            }
+        });
+    }
+
+    thread::sleep(Duration::from_secs(2));
+
+    server.kill().ok();
+    server.wait().ok();
+
+    let (stale_exists, fresh_exists, regular_exists) = (stale.exists(), fresh.exists(), regular.exists());
+    remove_dir_all(root).ok();
+
+    assert!(!stale_exists, "Stale partial upload was not removed");
+    assert!(fresh_exists, "Recent partial upload was removed");
+    assert!(regular_exists, "A regular file was removed");
+}
+
+#[test]
+fn test_one_upload_per_destination() {
+    let root = Path::new("partials_claim_test_root");
+    create_dir_all(root).expect("Failed to create test root");
+
+    let mut server = Command::new("../target/debug/remcp-serv")
+        .arg("--root")
+        .arg(root)
+        .args(["--listen", "127.0.0.1:8010", "--transfer-rate", "0"])
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .spawn()
+        .expect("Failed to start server");
+    thread::sleep(Duration::from_secs(2));
+
+    // Sends `PUT shared.bin 10` and returns the reply, leaving the upload open
+    let start_upload = || {
+        let stream = TcpStream::connect("127.0.0.1:8010").expect("Failed to connect");
+        stream.set_read_timeout(Some(Duration::from_secs(10))).unwrap();
+        let mut reader = BufReader::new(stream.try_clone().unwrap());
+        let mut line = String::new();
+        (&stream).write_all(b"HELLO 1 -\nPUT shared.bin 10\n").unwrap();
+        reader.read_line(&mut line).unwrap();
+        line.clear();
+        reader.read_line(&mut line).unwrap();
+        (stream, reader, line.trim_end().to_string())
+    };
+    let (mut first, mut first_reader, first_reply) = start_upload();
+    let (second, _, second_reply) = start_upload();
+    drop(second);
+
+    // The first upload is not disturbed and, once done, frees the destination
+    let mut next = String::new();
+    first_reader.read_line(&mut next).unwrap();
+    first.write_all(b"0123456789").unwrap();
+    // Without checksums nothing is answered; the server hangs up once the file is in place
+    let closed = first_reader.read_line(&mut next).unwrap() == 0;
+    drop((first, first_reader));
+    let (_third, _, third_reply) = start_upload();
+
+    server.kill().ok();
+    server.wait().ok();
+    let uploaded = read(root.join("shared.bin")).unwrap_or_default();
+    remove_dir_all(root).ok();
+
+    assert_eq!(first_reply, "OK 0");
+    assert_eq!(second_reply, "ERR File error: 'shared.bin' is already being uploaded");
+    assert!(closed, "The first upload did not finish");
+    assert_eq!(uploaded, b"0123456789");
+    assert_eq!(third_reply, "OK 0");
+}
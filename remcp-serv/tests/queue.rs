@@ -0,0 +1,122 @@
+use std::fs::{create_dir_all, read, remove_dir_all, remove_file, write};
+use std::process::{Command, Stdio};
+use std::thread;
+use std::time::Duration;
+
+mod common;
+use common::{start_server, Client};
+
+#[test]
+fn test_queue_admits_in_order() {
+    let mut server =
+        start_server(&["--max-clients", "1", "--queue-size", "2", "--queue-timeout", "4", "--listen", "127.0.0.1:7984"]);
+
+    let mut first = Client::connect(("127.0.0.1", 7984));
+    first.send("HELLO 1 session");
+    let first_hello = first.reply();
+
+    let mut second = Client::connect(("127.0.0.1", 7984));
+    second.send("HELLO 1 session");
+    let second_queued = second.reply();
+    let mut third = Client::connect(("127.0.0.1", 7984));
+    let third_queued = third.reply();
+    let mut fourth = Client::connect(("127.0.0.1", 7984));
+    let fourth_reply = fourth.reply();
+
+    // Without news, waiting clients are still reminded of their place
+    let third_reminder = third.reply();
+
+    first.send("QUIT");
+    let first_quit = first.reply();
+    let second_hello = second.reply_after("QUEUED 1");
+    let third_moved = third.reply_after("QUEUED 2");
+
+    // The second client keeps its slot, so the third one times out
+    let mut third_rest = Vec::new();
+    loop {
+        let line = third.reply();
+        if line.is_empty() {
+            break;
+        }
+        third_rest.push(line);
+    }
+
+    server.kill().ok();
+    server.wait().ok();
+
+    assert_eq!(first_hello, "HELLO 1 session");
+    assert_eq!(second_queued, "QUEUED 1");
+    assert_eq!(third_queued, "QUEUED 2");
+    assert!(fourth_reply.starts_with("ERR Server is busy (retry after"), "Unexpected reply: {}", fourth_reply);
+    assert!(fourth_reply.ends_with("queue position 3)"), "Unexpected reply: {}", fourth_reply);
+    assert_eq!(third_reminder, "QUEUED 2");
+    assert_eq!(first_quit, "OK");
+    assert_eq!(second_hello, "HELLO 1 session");
+    assert_eq!(third_moved, "QUEUED 1");
+    let last = third_rest.last().cloned().unwrap_or_default();
+    assert!(last.starts_with("ERR Server is busy"), "Queue wait did not time out: {:?}", third_rest);
+}
+
+#[test]
+fn test_queue_size_holds_under_a_burst() {
+    let mut server = start_server(&["--max-clients", "1", "--queue-size", "2", "--listen", "127.0.0.1:7997"]);
+
+    let mut holder = Client::connect(("127.0.0.1", 7997));
+    holder.send("HELLO 1 session");
+    holder.reply();
+
+    // All connect before any of them is answered
+    let mut burst: Vec<Client> = (0..8).map(|_| Client::connect(("127.0.0.1", 7997))).collect();
+    let replies: Vec<String> = burst.iter_mut().map(Client::reply).collect();
+
+    server.kill().ok();
+    server.wait().ok();
+
+    let queued = replies.iter().filter(|reply| reply.starts_with("QUEUED ")).count();
+    let busy = replies.iter().filter(|reply| reply.starts_with("ERR Server is busy")).count();
+    assert_eq!(queued, 2, "Unexpected replies: {:?}", replies);
+    assert_eq!(busy, 6, "Unexpected replies: {:?}", replies);
+    assert_eq!(replies[..2], ["QUEUED 1", "QUEUED 2"]);
+}
+
+#[test]
+fn test_client_waits_in_queue() {
+    let root = "queue_test_root";
+    create_dir_all(root).expect("Failed to create test root");
+    write(format!("{}/queued.txt", root), b"served in order").expect("Failed to write test file");
+    let local = "queue_test_download.txt";
+
+    let mut server =
+        start_server(&["--root", root, "--max-clients", "1", "--queue-size", "1", "--listen", "127.0.0.1:7985"]);
+
+    let mut holder = Client::connect(("127.0.0.1", 7985));
+    holder.send("HELLO 1 session");
+    holder.reply();
+
+    let client = Command::new("../target/debug/remcp")
+        .args(["127.0.0.1:7985:queued.txt", local])
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .expect("Failed to start client");
+    thread::sleep(Duration::from_secs(1));
+    holder.send("QUIT");
+    holder.reply();
+
+    let output = client.wait_with_output().expect("Failed to wait for client");
+    println!("[CLIENT STDOUT] {}", String::from_utf8_lossy(&output.stdout));
+    eprintln!("[CLIENT STDERR] {}", String::from_utf8_lossy(&output.stderr));
+    let content = read(local).unwrap_or_default();
+
+    server.kill().ok();
+    server.wait().ok();
+    remove_dir_all(root).ok();
+    remove_file(local).ok();
+
+    assert!(output.status.success(), "Queued download failed");
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(stdout.contains("Waiting in queue at position 1"), "Client did not report its place");
+    assert!(stdout.contains("Attempt 1/"), "Client should get in on its first attempt");
+    assert!(!stdout.contains("Attempt 2/"), "Client should get in on its first attempt");
+    assert_eq!(content, b"served in order");
+}
@@ -0,0 +1,85 @@
+use std::fs::{create_dir_all, remove_dir_all, write};
+use std::io::{BufRead, BufReader, Read};
+use std::process::{Command, Stdio};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+mod common;
+use common::Client;
+
+#[test]
+fn test_session_serves_many_requests() {
+    let root = "session_test_root";
+    create_dir_all(format!("{}/upload", root)).expect("Failed to create test root");
+    write(format!("{}/a.txt", root), b"first").expect("Failed to write test file");
+    write(format!("{}/b.txt", root), b"second").expect("Failed to write test file");
+
+    let sources = "session_test_sources";
+    create_dir_all(sources).expect("Failed to create source directory");
+    for name in ["one.txt", "two.txt", "three.txt"] {
+        write(format!("{}/{}", sources, name), name).expect("Failed to write source file");
+    }
+
+    let mut server = Command::new("../target/debug/remcp-serv")
+        .arg("--root")
+        .arg(root)
+        .arg("--idle-timeout")
+        .arg("1")
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .expect("Failed to start server");
+
+    let server_lines = Arc::new(Mutex::new(Vec::new()));
+    if let Some(stdout) = server.stdout.take() {
+        let lines = Arc::clone(&server_lines);
+        thread::spawn(move || {
+            for line in BufReader::new(stdout).lines().map_while(Result::ok) {
+                println!("[SERVER STDOUT] {}", line);
+                lines.lock().unwrap().push(line);
+            }
+        });
+    }
+
+    thread::sleep(Duration::from_secs(2));
+
+    let mut client = Client::connect("127.0.0.1:7878");
+    let hello = client.request("HELLO 1 session");
+    let first = client.request("STAT a.txt");
+    let second = client.request("STAT b.txt");
+    let quit = client.request("QUIT");
+    let mut rest = Vec::new();
+    client.reader.read_to_end(&mut rest).ok();
+
+    let mut idle = Client::connect("127.0.0.1:7878");
+    idle.request("HELLO 1 session");
+    thread::sleep(Duration::from_secs(3));
+    let mut byte = [0u8; 1];
+    let idle_read = idle.reader.read(&mut byte);
+
+    server_lines.lock().unwrap().clear();
+    let upload = Command::new("../target/debug/remcp")
+        .arg(format!("{}/*.txt", sources))
+        .arg("127.0.0.1:upload/")
+        .output()
+        .expect("Failed to run client");
+    thread::sleep(Duration::from_millis(500));
+    let connections = server_lines.lock().unwrap().iter().filter(|line| line.starts_with("Client connected")).count();
+
+    server.kill().ok();
+    server.wait().ok();
+    remove_dir_all(root).ok();
+    remove_dir_all(sources).ok();
+
+    assert_eq!(hello, "HELLO 1 session");
+    assert!(first.starts_with("STAT file 5 "), "Unexpected reply: {}", first);
+    assert!(second.starts_with("STAT file 6 "), "Unexpected reply: {}", second);
+    assert_eq!(quit, "OK");
+    assert!(rest.is_empty(), "Server sent data after QUIT");
+
+    assert!(matches!(idle_read, Ok(0)), "Idle session was not closed: {:?}", idle_read);
+
+    assert!(upload.status.success(), "Multi-file upload failed");
+    assert_eq!(connections, 1, "Upload did not reuse its session");
+}
@@ -0,0 +1,237 @@
+use std::fs::{create_dir_all, read, remove_dir_all, write};
+use std::process::{Child, Command, Stdio};
+use std::thread;
+use std::time::{Duration, Instant};
+
+mod common;
+use common::start_server;
+
+const FILE_SIZE: usize = 40_000;
+
+/// Downloads `count` files at once and returns how long they took together.
+fn download_all(port: u16, count: usize, work_dir: &str) -> Duration {
+    let start = Instant::now();
+    let clients: Vec<Child> = (0..count)
+        .map(|i| {
+            Command::new("../target/debug/remcp")
+                .arg(format!("127.0.0.1:{}:file_{}.bin", port, i))
+                .arg(format!("{}/file_{}.bin", work_dir, i))
+                .stdout(Stdio::null())
+                .stderr(Stdio::null())
+                .spawn()
+                .expect("Failed to start client")
+        })
+        .collect();
+    for mut client in clients {
+        assert!(client.wait().expect("Failed to wait for client").success(), "A download failed");
+    }
+    start.elapsed()
+}
+
+#[test]
+fn test_aggregate_rate_is_capped() {
+    let root = "throughput_test_root";
+    let work_dir = "throughput_test_downloads";
+    create_dir_all(root).expect("Failed to create test root");
+    create_dir_all(work_dir).expect("Failed to create work directory");
+    let content: Vec<u8> = (0..FILE_SIZE).map(|i| (i % 253) as u8).collect();
+    for i in 0..3 {
+        write(format!("{}/file_{}.bin", root, i), &content).expect("Failed to write test file");
+    }
+
+    // 120000 bytes at 20000 bytes/s, with every chunk paced
+    let mut server = start_server(&["--root", root, "--listen", "127.0.0.1:7988", "--transfer-rate", "20000"]);
+    let capped = download_all(7988, 3, work_dir);
+    server.kill().ok();
+    server.wait().ok();
+    let downloads: Vec<Vec<u8>> = (0..3).map(|i| read(format!("{}/file_{}.bin", work_dir, i)).unwrap_or_default()).collect();
+
+    // A burst as large as the file lets it through at once
+    remove_dir_all(work_dir).ok();
+    create_dir_all(work_dir).expect("Failed to create work directory");
+    let mut server =
+        start_server(&["--root", root, "--listen", "127.0.0.1:7989", "--transfer-rate", "20000", "--burst", "50000"]);
+    let burst = download_all(7989, 1, work_dir);
+    server.kill().ok();
+    server.wait().ok();
+
+    remove_dir_all(root).ok();
+    remove_dir_all(work_dir).ok();
+
+    for download in downloads {
+        assert!(download == content, "Downloaded content differs");
+    }
+    let expected = Duration::from_secs(6);
+    assert!(capped >= expected.mul_f64(0.9), "Faster than the rate allows: {:?}", capped);
+    assert!(capped <= expected.mul_f64(1.3), "Slower than the rate allows: {:?}", capped);
+    assert!(burst < Duration::from_secs(1), "Burst did not go out at once: {:?}", burst);
+}
+
+#[test]
+fn test_weights_split_the_rate() {
+    let root = "weights_test_root";
+    let work_dir = "weights_test_downloads";
+    create_dir_all(root).expect("Failed to create test root");
+    create_dir_all(work_dir).expect("Failed to create work directory");
+    let content: Vec<u8> = (0..FILE_SIZE).map(|i| (i % 251) as u8).collect();
+    write(format!("{}/shared.bin", root), &content).expect("Failed to write test file");
+
+    // Both are local, but IPv6 loopback counts as another address
+    let mut server = Command::new("../target/debug/remcp-serv")
+        .args(["--root", root, "--listen", "[::1]:7990", "--listen", "127.0.0.1:7990"])
+        .args(["--transfer-rate", "20000", "--weight", "::1=3"])
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .spawn()
+        .expect("Failed to start server");
+    thread::sleep(Duration::from_secs(2));
+    let start = Instant::now();
+    let clients: Vec<(Child, String)> = [("heavy", "[::1]"), ("light", "127.0.0.1")]
+        .iter()
+        .map(|(name, host)| {
+            let local = format!("{}/{}.bin", work_dir, name);
+            let client = Command::new("../target/debug/remcp")
+                .arg(format!("{}:7990:shared.bin", host))
+                .arg(&local)
+                .stdout(Stdio::null())
+                .stderr(Stdio::null())
+                .spawn()
+                .expect("Failed to start client");
+            (client, local)
+        })
+        .collect();
+    let finished: Vec<(Duration, Vec<u8>)> = clients
+        .into_iter()
+        .map(|(mut client, local)| {
+            assert!(client.wait().expect("Failed to wait for client").success(), "A download failed");
+            (start.elapsed(), read(local).unwrap_or_default())
+        })
+        .collect();
+    server.kill().ok();
+    server.wait().ok();
+
+    remove_dir_all(root).ok();
+    remove_dir_all(work_dir).ok();
+
+    let (heavy, light) = (finished[0].0, finished[1].0);
+    for (_, download) in &finished {
+        assert!(*download == content, "Downloaded content differs");
+    }
+    // Three quarters of the rate while both download, then all of it for the light one
+    assert!(heavy < Duration::from_millis(3400), "Weighted client got no more than its even share: {:?}", heavy);
+    assert!(light >= Duration::from_millis(3600), "Faster than the rate allows: {:?}", light);
+    assert!(light < Duration::from_millis(4800), "Rate left unused after the heavy client finished: {:?}", light);
+}
+
+#[test]
+fn test_directions_are_limited_separately() {
+    let root = "directions_test_root";
+    let work_dir = "directions_test_files";
+    create_dir_all(root).expect("Failed to create test root");
+    create_dir_all(work_dir).expect("Failed to create work directory");
+    let content: Vec<u8> = (0..FILE_SIZE).map(|i| (i % 241) as u8).collect();
+    write(format!("{}/down.bin", root), &content).expect("Failed to write test file");
+    write(format!("{}/up.bin", work_dir), &content).expect("Failed to write test file");
+
+    let mut server = Command::new("../target/debug/remcp-serv")
+        .args(["--root", root, "--listen", "127.0.0.1:7991", "--stats-interval", "1"])
+        .args(["--download-rate", "20000", "--upload-rate", "0"])
+        .stdout(Stdio::piped())
+        .stderr(Stdio::null())
+        .spawn()
+        .expect("Failed to start server");
+    thread::sleep(Duration::from_secs(2));
+
+    let copy = |from: &str, to: &str| {
+        let start = Instant::now();
+        let status = Command::new("../target/debug/remcp")
+            .args([from, to])
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .status()
+            .expect("Failed to run client");
+        assert!(status.success(), "Copy from {} failed", from);
+        start.elapsed()
+    };
+    let upload = copy(&format!("{}/up.bin", work_dir), "127.0.0.1:7991:up.bin");
+    let download = copy("127.0.0.1:7991:down.bin", &format!("{}/down.bin", work_dir));
+    thread::sleep(Duration::from_millis(1500));
+
+    server.kill().ok();
+    let output = server.wait_with_output().expect("Failed to wait for server");
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    println!("[SERVER STDOUT] {}", stdout);
+    let uploaded = read(format!("{}/up.bin", root)).unwrap_or_default();
+    let downloaded = read(format!("{}/down.bin", work_dir)).unwrap_or_default();
+
+    remove_dir_all(root).ok();
+    remove_dir_all(work_dir).ok();
+
+    assert!(uploaded == content, "Uploaded content differs");
+    assert!(downloaded == content, "Downloaded content differs");
+    assert!(upload < Duration::from_secs(1), "Upload was held to the download rate: {:?}", upload);
+    assert!(download >= Duration::from_millis(1800), "Faster than the download rate allows: {:?}", download);
+    let last = stdout.lines().rfind(|line| line.starts_with("Stats:")).unwrap_or_default();
+    assert!(last.contains("downloads 40000 bytes"), "Downloads not reported: {}", last);
+    assert!(last.contains("limit 20000 B/s"), "Download limit not reported: {}", last);
+    assert!(last.contains("uploads 40000 bytes"), "Uploads not reported: {}", last);
+    assert!(last.ends_with("limit unlimited)"), "Upload limit not reported: {}", last);
+}
+
+#[test]
+fn test_connections_share_their_address_flow() {
+    let root = "flows_test_root";
+    let work_dir = "flows_test_downloads";
+    create_dir_all(root).expect("Failed to create test root");
+    create_dir_all(work_dir).expect("Failed to create work directory");
+    let content: Vec<u8> = (0..FILE_SIZE / 2).map(|i| (i % 239) as u8).collect();
+    write(format!("{}/shared.bin", root), &content).expect("Failed to write test file");
+
+    let mut server = Command::new("../target/debug/remcp-serv")
+        .args(["--root", root, "--listen", "[::1]:8009", "--listen", "127.0.0.1:8009"])
+        .args(["--transfer-rate", "20000"])
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .spawn()
+        .expect("Failed to start server");
+    thread::sleep(Duration::from_secs(2));
+
+    // Three connections from one address against one from another
+    let start = Instant::now();
+    let hosts = [("many_0", "[::1]"), ("many_1", "[::1]"), ("many_2", "[::1]"), ("single", "127.0.0.1")];
+    let clients: Vec<(Child, String)> = hosts
+        .iter()
+        .map(|(name, host)| {
+            let local = format!("{}/{}.bin", work_dir, name);
+            let client = Command::new("../target/debug/remcp")
+                .arg(format!("{}:8009:shared.bin", host))
+                .arg(&local)
+                .stdout(Stdio::null())
+                .stderr(Stdio::null())
+                .spawn()
+                .expect("Failed to start client");
+            (client, local)
+        })
+        .collect();
+    let finished: Vec<(Duration, Vec<u8>)> = clients
+        .into_iter()
+        .rev()
+        .map(|(mut client, local)| {
+            assert!(client.wait().expect("Failed to wait for client").success(), "A download failed");
+            (start.elapsed(), read(local).unwrap_or_default())
+        })
+        .collect();
+    server.kill().ok();
+    server.wait().ok();
+
+    remove_dir_all(root).ok();
+    remove_dir_all(work_dir).ok();
+
+    for (_, download) in &finished {
+        assert!(*download == content, "Downloaded content differs");
+    }
+    // Half of the rate for each address, rather than a quarter for each connection
+    let single = finished[0].0;
+    assert!(single >= Duration::from_millis(1800), "Faster than the rate allows: {:?}", single);
+    assert!(single < Duration::from_millis(3000), "Extra connections took a bigger share: {:?}", single);
+}
@@ -0,0 +1,46 @@
+use std::fs::remove_file;
+use std::io::Read;
+use std::time::{Duration, Instant};
+
+mod common;
+use common::{start_server, Client};
+
+/// How long the server takes to hang up on `client`, which must not be sent anything.
+fn time_until_closed(client: &mut Client) -> Duration {
+    let start = Instant::now();
+    let mut rest = Vec::new();
+    client.reader.read_to_end(&mut rest).ok();
+    start.elapsed()
+}
+
+#[test]
+fn test_stalled_clients_are_evicted() {
+    let mut server = start_server(&[
+        "--max-clients", "1", "--connect-timeout", "1", "--chunk-timeout", "1", "--idle-timeout", "30",
+        "--root", ".", "--listen", "127.0.0.1:7986",
+    ]);
+
+    // Never says HELLO
+    let silent_wait = time_until_closed(&mut Client::connect("127.0.0.1:7986"));
+
+    // Starts an upload and then stops sending
+    let mut stalled = Client::connect("127.0.0.1:7986");
+    let hello = stalled.request("HELLO 1 session");
+    let put = stalled.request("PUT timeouts_test_upload.bin 100");
+    let next = stalled.reply();
+    let stalled_wait = time_until_closed(&mut stalled);
+
+    // Both freed the only client slot
+    let next_hello = Client::connect("127.0.0.1:7986").request("HELLO 1 session");
+
+    server.kill().ok();
+    server.wait().ok();
+    remove_file(".timeouts_test_upload.bin.remcp-part").ok();
+
+    assert!(silent_wait < Duration::from_secs(5), "Silent client held for {:?}", silent_wait);
+    assert_eq!(hello, "HELLO 1 session");
+    assert_eq!(put, "OK 0");
+    assert!(next.starts_with("NEXT "), "Unexpected reply: {}", next);
+    assert!(stalled_wait < Duration::from_secs(5), "Stalled upload held for {:?}", stalled_wait);
+    assert_eq!(next_hello, "HELLO 1 session");
+}
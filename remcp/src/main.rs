@@ -1,37 +1,384 @@
-use std::time::Duration;
+use std::time::{Duration, UNIX_EPOCH};
 use std::{env, thread, process};
 use std::fs::{File, OpenOptions, rename};
-use std::io::{BufRead, BufReader, BufWriter, Read, Seek, SeekFrom, Write, Result};
-use std::net::TcpStream;
-use std::path::PathBuf;
-use shared_lib::{parse_server_response, normalize_path, ServerResponse, debug_println, debug_eprintln};
+use std::io::{self, BufReader, BufWriter, Read, Seek, SeekFrom, Write, Result};
+use std::net::{Shutdown, TcpStream, ToSocketAddrs};
+use std::sync::atomic::{AtomicBool, AtomicU32, Ordering};
+use std::sync::mpsc::channel;
+use std::sync::{Arc, Mutex};
+use std::thread::JoinHandle;
+use std::collections::hash_map::RandomState;
+use std::ffi::{OsStr, OsString};
+use std::hash::{BuildHasher, Hasher};
+use std::path::{Component, Path, PathBuf};
+use shared_lib::checksum::{crc32c, Sha256};
+use shared_lib::fmt_utils::{format_mode, format_timestamp, stat_json};
+use shared_lib::glob::{glob_match, is_glob};
+use shared_lib::mux::{read_frame, Demux, StreamWriter, MAX_STREAMS};
+use shared_lib::remote::parse_remote;
+use shared_lib::{
+    CAPABILITIES, CAP_CHECKSUMS, CAP_FILE_OPS, CAP_LIST, CAP_MUX, CAP_SESSION, CAP_STAT, PROTOCOL_VERSION, FileKind, FileStat, GetError, has_capability, normalize_path,
+    os_str_bytes, os_string_from_bytes, Request, Response, debug_println, debug_eprintln,
+};
 
-const MAX_RETRIES: usize = 5;
+/// Exit codes, so scripts can tell failures apart. Other failures exit with 1.
+/// Bad arguments or an impossible combination of them.
+const EXIT_USAGE: i32 = 2;
+/// The server could not be reached.
+const EXIT_CONNECTION: i32 = 3;
+/// The server was still busy after every retry.
+const EXIT_BUSY: i32 = 4;
+/// The server refused the request: missing file, access denied and the like.
+const EXIT_REMOTE: i32 = 5;
+/// Data kept failing its checksums.
+const EXIT_INTEGRITY: i32 = 6;
+/// A transfer stopped midway, or only some files of a batch were copied.
+const EXIT_PARTIAL: i32 = 7;
+
+/// Server port for remote paths that do not name one.
+static mut DEFAULT_PORT: u16 = 7878;
+
+/// Retries after the first attempt of a GET or PUT.
+static mut RETRIES: u32 = 4;
+/// Wait before the first retry; it doubles on each retry after that.
+static mut RETRY_DELAY: Duration = Duration::from_secs(1);
+/// Upper bound on the wait between two attempts.
+static mut RETRY_MAX_DELAY: Duration = Duration::from_secs(30);
+/// Randomizes the second half of each wait so clients turned away together
+/// do not all come back at the same moment.
+static mut RETRY_JITTER: bool = true;
+
+/// Time allowed to establish a connection; zero waits as long as the OS does.
+static mut CONNECT_TIMEOUT: Duration = Duration::from_secs(10);
+/// Time a reply or chunk may take to arrive, or to be sent, before the
+/// transfer counts as stalled; zero waits forever.
+static mut CHUNK_TIMEOUT: Duration = Duration::from_secs(30);
 
 struct EndpointPaths {
+    /// Server address as `host:port`, prefixed with `user@` when one is given.
     remote_host: String,
-    remote_path: String,
+    remote_path: OsString,
     local_path: PathBuf,
 }
 
-fn split_host_path(remote: &str, local_path: &str) -> EndpointPaths {
-    if let Some(idx) = remote.find(':') {
-        EndpointPaths {
-            remote_host: remote[..idx].to_string(),
-            remote_path: remote[idx + 1..].to_string(),
-            local_path: normalize_path(local_path),
+type SharedWriter = Arc<Mutex<Box<dyn Write + Send>>>;
+
+/// A conversation with the server that has completed the `HELLO` exchange:
+/// either a TCP connection of its own or one stream of a multiplexed `Link`.
+struct Connection {
+    host: String,
+    reader: BufReader<Box<dyn Read + Send>>,
+    writer: Box<dyn Write + Send>,
+    capabilities: Vec<String>,
+    /// The connection's own socket; `None` for a stream.
+    socket: Option<TcpStream>,
+}
+
+/// Connections released after a successful request to a server that keeps
+/// sessions open, waiting to be reused for the next request to that host.
+static IDLE_SESSIONS: Mutex<Vec<Connection>> = Mutex::new(Vec::new());
+
+/// Multiplexed connections, one per host, that new streams are opened on.
+static LINKS: Mutex<Vec<Arc<Link>>> = Mutex::new(Vec::new());
+
+/// A multiplexed connection. A background thread hands incoming frames to
+/// the stream they belong to.
+struct Link {
+    host: String,
+    socket: TcpStream,
+    writer: SharedWriter,
+    demux: Arc<Demux>,
+    next_stream: AtomicU32,
+    capabilities: Vec<String>,
+    closed: Arc<AtomicBool>,
+    receiver: Mutex<Option<JoinHandle<()>>>,
+}
+
+impl Link {
+    /// Takes over a connection that negotiated `mux` and starts reading frames.
+    fn start(connection: Connection) -> Result<Arc<Link>> {
+        let Connection { host, mut reader, writer, capabilities, socket } = connection;
+        let socket = socket.ok_or_else(|| io::Error::other("Only a socket can be multiplexed"))?;
+        // Idle links wait for frames indefinitely; each stream has its own timeout
+        socket.set_read_timeout(None)?;
+        let demux = Arc::new(Demux::new());
+        let closed = Arc::new(AtomicBool::new(false));
+
+        let receiver = {
+            let (demux, closed) = (Arc::clone(&demux), Arc::clone(&closed));
+            thread::spawn(move || {
+                while let Ok(Some((stream, payload))) = read_frame(&mut reader) {
+                    demux.deliver(stream, payload);
+                }
+                closed.store(true, Ordering::SeqCst);
+                demux.close_all();
+            })
+        };
+
+        let link = Arc::new(Link {
+            host,
+            socket,
+            writer: Arc::new(Mutex::new(writer)),
+            demux,
+            next_stream: AtomicU32::new(1),
+            capabilities,
+            closed,
+            receiver: Mutex::new(Some(receiver)),
+        });
+        LINKS.lock().unwrap().push(Arc::clone(&link));
+        Ok(link)
+    }
+
+    fn find(host: &str) -> Option<Arc<Link>> {
+        let mut links = LINKS.lock().unwrap();
+        links.retain(|link| !link.closed.load(Ordering::SeqCst));
+        links.iter().find(|link| link.host == host).cloned()
+    }
+
+    fn open_stream(&self) -> Connection {
+        let stream = self.next_stream.fetch_add(1, Ordering::SeqCst);
+        debug_println!("Opening stream {} to '{}'", stream, self.host);
+        let mut frames = self.demux.open(stream);
+        frames.set_read_timeout(chunk_timeout());
+        Connection {
+            host: self.host.clone(),
+            reader: BufReader::new(Box::new(frames)),
+            writer: Box::new(StreamWriter::new(stream, Arc::clone(&self.writer))),
+            capabilities: self.capabilities.clone(),
+            socket: None,
         }
-    } else {
-        EndpointPaths {
-            remote_host: remote.to_string(),
-            remote_path: ":".to_string(),
-            local_path: normalize_path(local_path),
+    }
+
+    /// Stops sending and waits for the server to close its side.
+    fn close(&self) {
+        let _ = self.socket.shutdown(Shutdown::Write);
+        if let Some(receiver) = self.receiver.lock().unwrap().take() {
+            let _ = receiver.join();
+        }
+    }
+}
+
+/// Connects to the first address `addr` resolves to that accepts, within
+/// the connect timeout.
+fn connect(addr: &str) -> Result<TcpStream> {
+    let addrs = addr.to_socket_addrs().map_err(|e| match e.raw_os_error() {
+        Some(_) => e,
+        // Name lookup failures carry no OS error or kind of their own
+        None => io::Error::new(io::ErrorKind::NotConnected, format!("Cannot resolve '{}': {}", addr, e)),
+    })?;
+    let timeout = unsafe { CONNECT_TIMEOUT };
+    let mut last_error = None;
+    for addr in addrs {
+        let attempt = if timeout.is_zero() {
+            TcpStream::connect(addr)
+        } else {
+            TcpStream::connect_timeout(&addr, timeout)
+        };
+        match attempt {
+            Ok(stream) => return Ok(stream),
+            Err(e) => last_error = Some(e),
+        }
+    }
+    Err(last_error.unwrap_or_else(|| {
+        io::Error::new(io::ErrorKind::NotConnected, format!("Cannot resolve '{}': no addresses", addr))
+    }))
+}
+
+fn chunk_timeout() -> Option<Duration> {
+    let timeout = unsafe { CHUNK_TIMEOUT };
+    (!timeout.is_zero()).then_some(timeout)
+}
+
+impl Connection {
+    fn open(host: &str) -> Result<Connection> {
+        if let Some(connection) = Connection::reuse(host) {
+            return Ok(connection);
+        }
+        if let Some(link) = Link::find(host) {
+            return Ok(link.open_stream());
+        }
+
+        // Hosts never contain `@`, while user names might
+        let (user, addr) = match host.rsplit_once('@') {
+            Some((user, addr)) => (Some(user.to_string()), addr),
+            None => (None, host),
+        };
+        let stream = connect(addr)?;
+        debug_println!("Connected to server at '{}'", addr);
+        let chunk_timeout = chunk_timeout();
+        stream.set_read_timeout(chunk_timeout)?;
+        stream.set_write_timeout(chunk_timeout)?;
+
+        let mut connection = Connection {
+            host: host.to_string(),
+            reader: BufReader::new(Box::new(stream.try_clone()?)),
+            writer: Box::new(BufWriter::new(stream.try_clone()?)),
+            capabilities: Vec::new(),
+            socket: Some(stream),
+        };
+        connection.handshake(user)?;
+        if connection.has_capability(CAP_MUX) {
+            return Ok(Link::start(connection)?.open_stream());
+        }
+        Ok(connection)
+    }
+
+    fn reuse(host: &str) -> Option<Connection> {
+        let mut idle = IDLE_SESSIONS.lock().unwrap();
+        while let Some(idx) = idle.iter().position(|connection| connection.host == host) {
+            let connection = idle.swap_remove(idx);
+            if connection.is_alive() {
+                debug_println!("Reusing session with '{}'", host);
+                return Some(connection);
+            }
+        }
+        None
+    }
+
+    /// Whether the server still holds the session open. A closed one reads
+    /// as EOF; an open idle one has nothing to read.
+    fn is_alive(&self) -> bool {
+        let Some(stream) = &self.socket else {
+            return false;
+        };
+        if !self.reader.buffer().is_empty() {
+            return false;
+        }
+        if stream.set_nonblocking(true).is_err() {
+            return false;
+        }
+        let mut byte = [0u8; 1];
+        let idle = matches!(stream.peek(&mut byte), Err(e) if e.kind() == io::ErrorKind::WouldBlock);
+        stream.set_nonblocking(false).is_ok() && idle
+    }
+
+    /// Hands the connection back for reuse once a request has completed.
+    /// Servers without sessions close after a transfer, so it is dropped,
+    /// and so is a stream, which is cheap to open again.
+    fn release(self) {
+        if self.socket.is_some() && self.has_capability(CAP_SESSION) {
+            IDLE_SESSIONS.lock().unwrap().push(self);
+        }
+    }
+
+    fn handshake(&mut self, user: Option<String>) -> Result<()> {
+        let hello = Request::Hello {
+            version: PROTOCOL_VERSION,
+            capabilities: CAPABILITIES.iter().map(|cap| cap.to_string()).collect(),
+            user,
+        };
+        hello.encode(&mut self.writer)?;
+
+        // A full server may hold the connection in its queue before answering
+        let mut queued_at = None;
+        let reply = loop {
+            match Response::decode(&mut self.reader)? {
+                Response::Queued(position) => {
+                    if queued_at != Some(position) {
+                        println!("Server is full. Waiting in queue at position {}...", position);
+                        queued_at = Some(position);
+                    }
+                }
+                reply => break reply,
+            }
+        };
+
+        match reply {
+            Response::Hello { version, capabilities } if version == PROTOCOL_VERSION => {
+                debug_println!("Handshake complete. Capabilities: {:?}", capabilities);
+                self.capabilities = capabilities;
+                Ok(())
+            }
+            Response::Hello { version, .. } => {
+                Err(GetError::VersionMismatch { client: PROTOCOL_VERSION, server: version }.into())
+            }
+            // Servers predating the handshake reject HELLO as an unknown command
+            Response::Err(GetError::UnknownCommand) => {
+                Err(GetError::VersionMismatch { client: PROTOCOL_VERSION, server: 0 }.into())
+            }
+            Response::Err(err) => Err(err.into()),
+            other => Err(io::Error::other(format!("Unexpected handshake response: '{}'", other))),
+        }
+    }
+
+    fn has_capability(&self, cap: &str) -> bool {
+        has_capability(&self.capabilities, cap)
+    }
+
+    fn stat(&mut self, path: &OsStr) -> Result<FileStat> {
+        Request::Stat { path: path.to_os_string() }.encode(&mut self.writer)?;
+        match Response::decode(&mut self.reader)? {
+            Response::Stat(stat) => {
+                debug_println!("STAT '{}': {}", path.to_string_lossy(), stat);
+                Ok(stat)
+            }
+            Response::Err(err) => Err(err.into()),
+            other => Err(io::Error::other(format!("Unexpected '{}' in STAT", other))),
+        }
+    }
+
+    /// Sends a request that the server answers with a bare `OK`.
+    fn expect_ok(&mut self, request: &Request) -> Result<()> {
+        request.encode(&mut self.writer)?;
+        debug_println!("Sent '{}'", request);
+        match Response::decode(&mut self.reader)? {
+            Response::Ok(_) => Ok(()),
+            Response::Err(err) => Err(err.into()),
+            other => Err(io::Error::other(format!("Unexpected '{}' response", other))),
+        }
+    }
+
+    /// Sends `LIST` and hands every `ENTRY` to `on_entry` as it arrives.
+    fn list<F>(&mut self, path: &OsStr, recursive: bool, pattern: Option<&OsStr>, mut on_entry: F) -> Result<u64>
+    where
+        F: FnMut(OsString, FileStat),
+    {
+        let request = Request::List {
+            path: path.to_os_string(),
+            recursive,
+            pattern: pattern.map(OsStr::to_os_string),
+        };
+        request.encode(&mut self.writer)?;
+
+        loop {
+            match Response::decode(&mut self.reader)? {
+                Response::Entry { path, stat } => on_entry(path, stat),
+                Response::Ok(Some(count)) => return Ok(count),
+                Response::Err(err) => return Err(err.into()),
+                other => return Err(io::Error::other(format!("Unexpected '{}' in LIST", other))),
+            }
         }
     }
 }
 
-fn determine_offset_and_part_path(local_path: &PathBuf) -> (u64, PathBuf) {
-    let part_path = local_path.with_extension("part");
+fn is_remote(arg: &OsStr) -> bool {
+    parse_remote(arg).is_some()
+}
+
+/// Splits a remote path, in any form `parse_remote` accepts, into the
+/// server address and the path on the server. `main` rejects malformed
+/// remote paths up front, so `remote` is known to be a valid one.
+fn split_host_path(remote: &OsStr, local_path: &OsStr) -> EndpointPaths {
+    let spec = parse_remote(remote)
+        .and_then(|spec| spec.ok())
+        .expect("remote paths are validated before use");
+    let address = spec.address(unsafe { DEFAULT_PORT });
+    EndpointPaths {
+        remote_host: match &spec.user {
+            Some(user) => format!("{}@{}", user, address),
+            None => address,
+        },
+        remote_path: spec.path,
+        local_path: normalize_path(local_path),
+    }
+}
+
+/// The partial file is named after the whole file name, so that `a.txt` and
+/// `a.bin` downloading side by side do not share one.
+fn determine_offset_and_part_path(local_path: &Path) -> (u64, PathBuf) {
+    let mut part_path = local_path.as_os_str().to_os_string();
+    part_path.push(".part");
+    let part_path = PathBuf::from(part_path);
     let offset = if let Ok(metadata) = std::fs::metadata(&part_path) {
         metadata.len()
     } else {
@@ -40,68 +387,131 @@ fn determine_offset_and_part_path(local_path: &PathBuf) -> (u64, PathBuf) {
     (offset, part_path)
 }
 
+fn modified_secs(path: &Path) -> Option<u64> {
+    let modified = std::fs::metadata(path).ok()?.modified().ok()?;
+    Some(modified.duration_since(UNIX_EPOCH).ok()?.as_secs())
+}
+
+/// Whether the local file already matches the remote one by size and mtime.
+fn is_up_to_date(local_path: &Path, remote: &FileStat) -> bool {
+    match std::fs::metadata(local_path) {
+        Ok(metadata) => {
+            metadata.is_file()
+                && metadata.len() == remote.size
+                && modified_secs(local_path) == Some(remote.mtime)
+        }
+        Err(_) => false,
+    }
+}
+
+/// A part file only continues the remote file if it is not longer than it
+/// and was written after the remote file last changed.
+fn is_part_valid(part_path: &Path, offset: u64, remote: &FileStat) -> bool {
+    offset <= remote.size && modified_secs(part_path).is_none_or(|part_mtime| part_mtime >= remote.mtime)
+}
+
+/// How a failed attempt is retried, if at all.
+enum Retry {
+    /// Right away: the next attempt resumes from the last verified offset.
+    Now,
+    /// After the backoff delay, to give the server or network time to recover.
+    Backoff,
+    /// After the wait a busy server asked for.
+    After(Duration),
+}
+
+/// Decides from the error itself whether another attempt can help.
+fn retry_kind(err: &io::Error) -> Option<Retry> {
+    if let Some(err) = GetError::from_io(err) {
+        return match err {
+            GetError::ServerBusy(Some(hint)) => Some(Retry::After(Duration::from_secs(hint.retry_after))),
+            GetError::ServerBusy(None) | GetError::LimitReached(_) => Some(Retry::Backoff),
+            GetError::IntegrityError(_) => Some(Retry::Now),
+            _ => None,
+        };
+    }
+    match err.kind() {
+        io::ErrorKind::ConnectionRefused
+        | io::ErrorKind::ConnectionReset
+        | io::ErrorKind::ConnectionAborted
+        | io::ErrorKind::BrokenPipe
+        | io::ErrorKind::TimedOut
+        // An expired read timeout, on some platforms
+        | io::ErrorKind::WouldBlock
+        | io::ErrorKind::Interrupted
+        // The server went away before the transfer was complete
+        | io::ErrorKind::UnexpectedEof => Some(Retry::Backoff),
+        _ => None,
+    }
+}
+
+/// Exponential backoff before retry number `retry`, capped at the maximum
+/// delay. With jitter, the wait is a random point in its upper half.
+fn backoff_delay(retry: u32) -> Duration {
+    let (base, max, jitter) = unsafe { (RETRY_DELAY, RETRY_MAX_DELAY, RETRY_JITTER) };
+    let delay = base.saturating_mul(1 << retry.saturating_sub(1).min(31)).min(max);
+    if !jitter {
+        return delay;
+    }
+    let half = delay / 2;
+    half + half.mul_f64(random_fraction())
+}
+
+/// A value in `[0, 1)`, random enough to spread retries apart.
+fn random_fraction() -> f64 {
+    let random = RandomState::new().build_hasher().finish();
+    (random >> 11) as f64 / (1u64 << 53) as f64
+}
+
 fn try_operation<F>(operation: F, operation_name: &str, paths: EndpointPaths) -> Result<()>
 where
     F: Fn(&EndpointPaths) -> Result<()>,
 {
+    let retries = unsafe { RETRIES };
     let mut attempt = 0;
 
     loop {
         attempt += 1;
         println!(
             "Attempt {}/{} to {} the file...",
-            attempt, MAX_RETRIES, operation_name
+            attempt, retries + 1, operation_name
         );
 
-        match operation(&paths) {
+        let e = match operation(&paths) {
             Ok(_) => {
                 println!("{} operation completed successfully.", operation_name);
                 return Ok(());
             }
-            Err(e) => {
-                if e.to_string().contains("Server is busy") {
-                    if attempt >= MAX_RETRIES {
-                        eprintln!(
-                            "Exceeded maximum retries due to 'Server is busy'. Aborting {} operation.",
-                            operation_name
-                        );
-                        return Err(e);
-                    }
-                    eprintln!(
-                        "Server is busy. Retrying {}/{} in 5 seconds...",
-                        attempt, MAX_RETRIES
-                    );
-                    thread::sleep(Duration::from_secs(5));
-                } else if let Some(os_code) = e.raw_os_error() {
-                    match os_code {
-                        10054 | 104 | 110 => {
-                            // 10054: Connection reset by peer (Windows)
-                            // 104: Connection reset by peer (Linux)
-                            // 110: Connection timed out (Linux)
-                            if attempt >= MAX_RETRIES {
-                                eprintln!(
-                                    "Exceeded maximum retries due to a retryable error. Aborting {} operation.",
-                                    operation_name
-                                );
-                                return Err(e);
-                            }
-                            eprintln!(
-                                "Retryable OS error encountered. Retrying {}/{} in 5 seconds... Error: {}",
-                                attempt, MAX_RETRIES, e
-                            );
-                            thread::sleep(Duration::from_secs(5));
-                        }
-                        _ => {
-                            eprintln!("Unexpected OS error during {} operation: {}", operation_name, e);
-                            return Err(e);
-                        }
-                    }
-                } else {
-                    eprintln!("Unexpected error during {} operation: {}", operation_name, e);
-                    return Err(e);
-                }
-            }
+            Err(e) => e,
+        };
+        let Some(retry) = retry_kind(&e) else {
+            eprintln!("Unexpected error during {} operation: {}", operation_name, e);
+            return Err(e);
+        };
+        if attempt > retries {
+            eprintln!(
+                "Exceeded maximum retries ({}). Aborting {} operation: {}",
+                retries, operation_name, e
+            );
+            return Err(e);
         }
+        let delay = match retry {
+            Retry::Now => {
+                eprintln!(
+                    "{}. Retrying {}/{} from the last verified offset...",
+                    e, attempt, retries
+                );
+                continue;
+            }
+            Retry::Backoff => backoff_delay(attempt),
+            // The server knows its queue best, but the user's limit still holds
+            Retry::After(wait) => wait.min(unsafe { RETRY_MAX_DELAY }),
+        };
+        eprintln!(
+            "{}. Retrying {}/{} in {:.1} seconds...",
+            e, attempt, retries, delay.as_secs_f64()
+        );
+        thread::sleep(delay);
     }
 }
 
@@ -114,7 +524,7 @@ fn try_put(paths: EndpointPaths) -> Result<()> {
 }
 
 fn do_get(paths: &EndpointPaths) -> Result<()> {
-    let (offset, part_path) = determine_offset_and_part_path(&paths.local_path);
+    let (mut offset, part_path) = determine_offset_and_part_path(&paths.local_path);
 
     debug_println!(
         "Starting GET operation from '{}' to local path '{}', offset={}",
@@ -122,159 +532,204 @@ fn do_get(paths: &EndpointPaths) -> Result<()> {
         paths.local_path.display(),
         offset
     );
-    let addr = format!("{}:7878", paths.remote_host);
-    let stream = TcpStream::connect(&addr)?;
-    debug_println!("Connected to server at '{}'", addr);
-
-    let mut writer = BufWriter::new(&stream);
-    writeln!(writer, "GET {} {}", paths.remote_path, offset)?;
-    writer.flush()?;
-    debug_println!("Sent GET command: path='{}', offset={}", paths.remote_path, offset);
-
-    let mut reader = BufReader::new(&stream);
-    let mut response = String::new();
-    reader.read_line(&mut response)?;
-    let response = response.trim_end();
+    let mut conn = Connection::open(&paths.remote_host)?;
+    let checksums = conn.has_capability(CAP_CHECKSUMS);
+
+    let remote_stat = if conn.has_capability(CAP_STAT) {
+        Some(conn.stat(&paths.remote_path)?)
+    } else {
+        None
+    };
+    if let Some(stat) = &remote_stat {
+        if stat.kind != FileKind::File {
+            return Err(GetError::FileError(format!("'{}' is not a regular file (use -r for directories)", paths.remote_path.to_string_lossy())).into());
+        }
+        if is_up_to_date(&paths.local_path, stat) {
+            println!("'{}' is up to date. Skipping download.", paths.local_path.display());
+            conn.release();
+            return Ok(());
+        }
+        if offset > 0 && !is_part_valid(&part_path, offset, stat) {
+            println!("Remote file changed since '{}' was written. Restarting download.", part_path.display());
+            std::fs::remove_file(&part_path)?;
+            offset = 0;
+        }
+    }
+
+    let request = Request::Get { path: paths.remote_path.clone(), offset };
+    request.encode(&mut conn.writer)?;
+    debug_println!("Sent GET command: path='{}', offset={}", paths.remote_path.to_string_lossy(), offset);
+
+    let response = Response::decode(&mut conn.reader)?;
     debug_println!("Server response: '{}'", response);
 
-    match parse_server_response(response) {
-        ServerResponse::Error(err) => {
+    let remaining_size = match response {
+        Response::Err(err) => {
             debug_eprintln!("Error received from server: {}", err);
-            return Err(std::io::Error::new(std::io::ErrorKind::Other, format!("{}", err)));
+            return Err(err.into());
         },
-        ServerResponse::Ok => {
-            let parts: Vec<&str> = response.split_whitespace().collect();
-            if parts.len() < 2 {
-                return Err(std::io::Error::new(std::io::ErrorKind::Other, "Invalid server response format"));
-            }
-            let remaining_size: u64 = parts[1].parse().unwrap_or(0);
-            debug_println!("Remaining size to download: {}", remaining_size);
+        Response::Ok(None) => {
+            return Err(std::io::Error::other("Invalid server response format"));
+        },
+        Response::Ok(Some(remaining_size)) => remaining_size,
+        other => {
+            eprintln!("Unexpected '{}' response in GET operation.", other);
+            return Err(std::io::Error::other(format!("Unexpected '{}' in GET", other)));
+        }
+    };
+    debug_println!("Remaining size to download: {}", remaining_size);
+    if remaining_size == 0 {
+        println!("No data to download.");
+    }
 
-            if remaining_size == 0 {
-                println!("No data to download.");
-                return Ok(());
+    let mut file = OpenOptions::new().read(true).write(true).create(true).truncate(false).open(&part_path)?;
+    let mut digest = Sha256::new();
+    if checksums {
+        // The server's digest covers the whole file, including what we already have
+        digest.update_from_reader(&mut file, offset)?;
+    }
+    file.seek(SeekFrom::Start(offset))?;
+    debug_println!("Opened partial file '{}', resuming at offset {}", part_path.display(), offset);
+
+    let mut received = 0u64;
+
+    while received < remaining_size {
+        let response = Response::decode(&mut conn.reader).inspect_err(|e| {
+            if e.kind() == std::io::ErrorKind::UnexpectedEof {
+                eprintln!("Server closed connection unexpectedly during GET.");
             }
+        })?;
+        debug_println!("Server 'NEXT' response: '{}'", response);
 
-            let mut file = OpenOptions::new().write(true).create(true).open(&part_path)?;
-            file.seek(SeekFrom::Start(offset))?;
-            debug_println!("Opened partial file '{}', resuming at offset {}", part_path.display(), offset);
+        match response {
+            Response::Next { size: chunk_size, crc } => {
+                let to_read = std::cmp::min(chunk_size as u64, remaining_size - received) as usize;
+                let mut buffer = vec![0u8; to_read];
 
-            let mut received = 0u64;
+                let bytes_read = conn.reader.read_exact(&mut buffer).map(|_| to_read).map_err(|e| {
+                    if e.kind() == std::io::ErrorKind::UnexpectedEof {
+                        let got = buffer.len() - conn.reader.buffer().len();
+                        return std::io::Error::new(std::io::ErrorKind::UnexpectedEof, format!("Connection lost, got {} instead of {}", got, to_read));
+                    }
+                    e
+                })?;
 
-            while received < remaining_size {
-                let mut line = String::new();
-                if reader.read_line(&mut line)? == 0 {
-                    eprintln!("Server closed connection unexpectedly during GET.");
-                    return Err(std::io::Error::new(std::io::ErrorKind::UnexpectedEof, "Server closed connection"));
-                }
-                let line = line.trim_end();
-                debug_println!("Server 'NEXT' response: '{}'", line);
-
-                match parse_server_response(line) {
-                    ServerResponse::Next(chunk_size) => {
-                        let to_read = std::cmp::min(chunk_size as u64, remaining_size - received) as usize;
-                        let mut buffer = vec![0u8; to_read];
-
-                        let bytes_read = reader.read_exact(&mut buffer).map(|_| to_read).or_else(|e| {
-                            if e.kind() == std::io::ErrorKind::UnexpectedEof {
-                                let got = buffer.len() - reader.buffer().len();
-                                return Err(std::io::Error::new(std::io::ErrorKind::UnexpectedEof, format!("Connection lost, got {} instead of {}", got, to_read)));
-                            }
-                            Err(e)
-                        })?;
-
-                        file.write_all(&buffer[..bytes_read])?;
-                        file.flush()?;
-                        received += bytes_read as u64;
-                        debug_println!("Received {} bytes. Total received: {} / {}", bytes_read, received, remaining_size);
-                    },
-                    ServerResponse::Ok => {
-                        debug_eprintln!("Unexpected 'OK' before finishing GET download.");
-                        break;
-                    },
-                    ServerResponse::Error(err) => {
-                        eprintln!("Error received from server during GET: {}", err);
-                        return Err(std::io::Error::new(std::io::ErrorKind::Other, format!("{}", err)));
+                if checksums {
+                    // A bad chunk is never written, so the retry resumes right at it
+                    if crc != Some(crc32c(&buffer)) {
+                        let chunk_offset = offset + received;
+                        eprintln!("Checksum mismatch in chunk at offset {}.", chunk_offset);
+                        return Err(GetError::IntegrityError(format!("chunk at offset {}", chunk_offset)).into());
                     }
+                    digest.update(&buffer);
                 }
+
+                file.write_all(&buffer[..bytes_read])?;
+                file.flush()?;
+                received += bytes_read as u64;
+                debug_println!("Received {} bytes. Total received: {} / {}", bytes_read, received, remaining_size);
+            },
+            Response::Ok(_) => {
+                debug_eprintln!("Unexpected 'OK' before finishing GET download.");
+                break;
+            },
+            Response::Err(err) => {
+                eprintln!("Error received from server during GET: {}", err);
+                return Err(err.into());
             }
+            other => {
+                eprintln!("Unexpected '{}' response during GET.", other);
+                return Err(std::io::Error::other("Invalid server response"));
+            }
+        }
+    }
 
-            if received == remaining_size {
-                debug_println!("Download complete. Renaming part file to final file.");
-                rename(part_path, &paths.local_path)?;
-            } else {
-                eprintln!("Incomplete download. Received {} bytes out of {}.", received, remaining_size);
-                return Err(std::io::Error::new(std::io::ErrorKind::UnexpectedEof, "Incomplete download"));
+    if received != remaining_size {
+        eprintln!("Incomplete download. Received {} bytes out of {}.", received, remaining_size);
+        return Err(std::io::Error::new(std::io::ErrorKind::UnexpectedEof, "Incomplete download"));
+    }
+
+    if checksums {
+        match Response::decode(&mut conn.reader)? {
+            Response::Done { digest: expected } if expected == digest.finish_hex() => {
+                debug_println!("File digest verified.");
+            }
+            Response::Done { .. } => {
+                // The part file does not match the remote file; start over next attempt
+                eprintln!("File digest mismatch. Discarding '{}'.", part_path.display());
+                drop(file);
+                std::fs::remove_file(&part_path)?;
+                return Err(GetError::IntegrityError("file digest mismatch".to_string()).into());
+            }
+            Response::Err(err) => return Err(err.into()),
+            other => {
+                eprintln!("Unexpected '{}' response instead of file digest.", other);
+                return Err(std::io::Error::other("Invalid server response"));
             }
-        },
-        ServerResponse::Next(_) => {
-            eprintln!("Unexpected 'NEXT' response in GET operation.");
-            return Err(std::io::Error::new(std::io::ErrorKind::Other, "Unexpected 'NEXT' in GET"));
         }
     }
 
+    debug_println!("Download complete. Renaming part file to final file.");
+    rename(part_path, &paths.local_path)?;
+    if let Some(stat) = &remote_stat {
+        // Matching mtimes let the next GET of an unchanged file be skipped
+        let modified = UNIX_EPOCH + Duration::from_secs(stat.mtime);
+        OpenOptions::new().write(true).open(&paths.local_path)?.set_modified(modified)?;
+    }
+    conn.release();
+
     println!("GET operation completed successfully.");
     Ok(())
 }
 
 fn do_put(paths: &EndpointPaths) -> Result<()> {
-    let (offset, part_path) = determine_offset_and_part_path(&paths.local_path);
-
     debug_println!(
-        "Starting PUT operation: local file to remote path '{}:{}', offset={}",
+        "Starting PUT operation: local file to remote path '{}:{}'",
         paths.remote_host,
-        paths.remote_path,
-        offset
+        paths.remote_path.to_string_lossy()
     );
     let total_size = std::fs::metadata(&paths.local_path)?.len();
     debug_println!("File size: {} bytes", total_size);
 
-    let addr = format!("{}:7878", paths.remote_host);
-    let stream = TcpStream::connect(&addr)?;
-    debug_println!("Connected to server at '{}'", addr);
+    let mut conn = Connection::open(&paths.remote_host)?;
+    let checksums = conn.has_capability(CAP_CHECKSUMS);
 
-    let mut writer = BufWriter::new(&stream);
-    writeln!(writer, "PUT {} {} {}", paths.remote_path, offset, total_size)?;
-    writer.flush()?;
-    debug_println!("Sent PUT command: path='{}', offset={}, total_size={}", paths.remote_path, offset, total_size);
+    let request = Request::Put { path: paths.remote_path.clone(), total_size };
+    request.encode(&mut conn.writer)?;
+    debug_println!("Sent PUT command: path='{}', total_size={}", paths.remote_path.to_string_lossy(), total_size);
 
-    let mut reader = BufReader::new(&stream);
-    let mut line = String::new();
-    reader.read_line(&mut line)?;
-    let mut line = line.trim_end().to_string();
-    debug_println!("Server initial response: '{}'", line);
+    let response = Response::decode(&mut conn.reader)?;
+    debug_println!("Server initial response: '{}'", response);
 
-    match parse_server_response(&line) {
-        ServerResponse::Error(err) => {
+    // The server reports how much of the upload it already holds
+    let offset = match response {
+        Response::Err(err) => {
             eprintln!("Error received from server: {}", err);
-            return Err(std::io::Error::new(std::io::ErrorKind::Other, format!("{}", err)));
+            return Err(err.into());
         },
-        ServerResponse::Ok => debug_println!("Server acknowledged PUT request. Starting file upload."),
+        Response::Ok(Some(offset)) if offset <= total_size => offset,
         _ => {
-            eprintln!("Unexpected server response: '{}'", line);
-            return Err(std::io::Error::new(std::io::ErrorKind::Other, "Invalid server response"));
+            eprintln!("Unexpected server response: '{}'", response);
+            return Err(std::io::Error::other("Invalid server response"));
         }
-    }
+    };
+    debug_println!("Server acknowledged PUT request. Resuming upload at offset {}.", offset);
 
     let mut file = File::open(&paths.local_path)?;
     file.seek(SeekFrom::Start(offset))?;
-    let mut part_file = OpenOptions::new().write(true).create(true).open(&part_path)?;
-    part_file.seek(SeekFrom::Start(offset))?;
-    debug_println!("Prepared partial file at '{}', resuming at offset {}", part_path.display(), offset);
 
     let mut sent = offset;
 
     while sent < total_size {
-        line.clear();
-        if reader.read_line(&mut line)? == 0 {
-            eprintln!("Server closed connection unexpectedly during PUT.");
-            return Err(std::io::Error::new(std::io::ErrorKind::UnexpectedEof, "Server closed connection"));
-        }
-        let line_buf = line.trim_end();
-        debug_println!("Server 'NEXT' response: '{}'", line_buf);
+        let response = Response::decode(&mut conn.reader).inspect_err(|e| {
+            if e.kind() == std::io::ErrorKind::UnexpectedEof {
+                eprintln!("Server closed connection unexpectedly during PUT.");
+            }
+        })?;
+        debug_println!("Server 'NEXT' response: '{}'", response);
 
-        match parse_server_response(line_buf) {
-            ServerResponse::Next(chunk_size) => {
+        match response {
+            Response::Next { size: chunk_size, .. } => {
                 let remaining = total_size - sent;
                 let to_read = std::cmp::min(chunk_size as u64, remaining) as usize;
                 let mut buffer = vec![0u8; to_read];
@@ -283,68 +738,841 @@ fn do_put(paths: &EndpointPaths) -> Result<()> {
                     debug_eprintln!("No more data to send but server expects more. Sent so far: {} bytes.", sent);
                     break;
                 }
-                writer.write_all(&buffer[..bytes_read])?;
-                writer.flush()?;
-                part_file.write_all(&buffer[..bytes_read])?;
-                part_file.flush()?;
+                if checksums {
+                    let data = Request::Data { size: bytes_read, crc: crc32c(&buffer[..bytes_read]) };
+                    data.encode(&mut conn.writer)?;
+                }
+                conn.writer.write_all(&buffer[..bytes_read])?;
+                conn.writer.flush()?;
                 sent += bytes_read as u64;
                 debug_println!("Sent {} bytes. Total sent: {} / {}", bytes_read, sent, total_size);
             },
-            ServerResponse::Ok => {
+            Response::Ok(_) => {
                 debug_println!("Server acknowledged file transfer completion.");
                 break;
             },
-            ServerResponse::Error(err) => {
+            Response::Err(err) => {
+                // The server keeps only what it verified; the retry asks it again
                 eprintln!("Error received from server: {}", err);
-                return Err(std::io::Error::new(std::io::ErrorKind::Other, format!("{}", err)));
+                return Err(err.into());
+            }
+            other => {
+                eprintln!("Unexpected '{}' response during PUT.", other);
+                return Err(std::io::Error::other("Invalid server response"));
             }
         }
     }
 
-    if sent == total_size {
-        debug_println!("Upload complete. Removing part file '{}'.", part_path.display());
-        std::fs::remove_file(part_path).ok();
-    } else {
+    if sent != total_size {
         eprintln!("Upload incomplete. Sent {} bytes out of {}.", sent, total_size);
         return Err(std::io::Error::new(std::io::ErrorKind::UnexpectedEof, "Upload incomplete"));
     }
 
+    if checksums {
+        // Also covers the prefix the server kept from an earlier attempt
+        let mut digest = Sha256::new();
+        digest.update_from_reader(&mut File::open(&paths.local_path)?, total_size)?;
+        Request::Done { digest: digest.finish_hex() }.encode(&mut conn.writer)?;
+
+        match Response::decode(&mut conn.reader)? {
+            Response::Ok(_) => debug_println!("Server verified the file digest."),
+            Response::Err(err) => {
+                eprintln!("Error received from server: {}", err);
+                return Err(err.into());
+            }
+            other => {
+                eprintln!("Unexpected '{}' response instead of digest confirmation.", other);
+                return Err(std::io::Error::other("Invalid server response"));
+            }
+        }
+    }
+
+    // Without checksums nothing confirms the upload, so only reuse verified ones
+    if checksums {
+        conn.release();
+    }
+
     println!("PUT operation completed successfully.");
     Ok(())
 }
 
+fn do_stat(paths: &EndpointPaths, json: bool) -> Result<()> {
+    let mut conn = Connection::open(&paths.remote_host)?;
+    if !conn.has_capability(CAP_STAT) {
+        return Err(io::Error::other("Server does not support STAT"));
+    }
+    let stat = conn.stat(&paths.remote_path)?;
+    let path = paths.remote_path.to_string_lossy();
+
+    if json {
+        println!("{}", stat_json(&path, &stat));
+    } else {
+        println!("  File: {}", path);
+        println!("  Type: {}", stat.kind.as_str());
+        println!("  Size: {}", stat.size);
+        println!("Access: {:04o} ({})", stat.mode, format_mode(stat.kind, stat.mode));
+        println!("Modify: {}", format_timestamp(stat.mtime));
+    }
+    Ok(())
+}
+
+/// Splits a remote path at its first wildcard component, so `dir/*.txt`
+/// lists `dir` filtered by `*.txt` and `dir/*/*.txt` by `*/*.txt`.
+fn split_pattern(remote_path: &OsStr) -> (OsString, Option<OsString>) {
+    let bytes = os_str_bytes(remote_path);
+    let mut start = 0;
+    for component in bytes.split(|&b| b == b'/') {
+        if is_glob(component) {
+            let dir = if start == 0 { b".".to_vec() } else { bytes[..start - 1].to_vec() };
+            return (os_string_from_bytes(dir), Some(os_string_from_bytes(bytes[start..].to_vec())));
+        }
+        start += component.len() + 1;
+    }
+
+    if bytes.is_empty() {
+        (OsString::from("."), None)
+    } else {
+        (remote_path.to_os_string(), None)
+    }
+}
+
+fn do_ls(paths: &EndpointPaths, recursive: bool, json: bool) -> Result<()> {
+    let mut conn = Connection::open(&paths.remote_host)?;
+    if !conn.has_capability(CAP_LIST) {
+        return Err(io::Error::other("Server does not support LIST"));
+    }
+    let (dir, pattern) = split_pattern(&paths.remote_path);
+
+    let mut objects = Vec::new();
+    let count = conn.list(&dir, recursive, pattern.as_deref(), |path, stat| {
+        let path = path.to_string_lossy();
+        if json {
+            objects.push(stat_json(&path, &stat));
+        } else {
+            let suffix = if stat.kind == FileKind::Dir { "/" } else { "" };
+            println!(
+                "{} {:>12} {} {}{}",
+                format_mode(stat.kind, stat.mode),
+                stat.size,
+                format_timestamp(stat.mtime),
+                path,
+                suffix
+            );
+        }
+    })?;
+
+    if json {
+        println!("[{}]", objects.join(","));
+    }
+    debug_println!("Listed {} entries.", count);
+    Ok(())
+}
+
+/// Sends a `DELETE`, `RMDIR`, `MKDIR` or `RENAME` and waits for its `OK`.
+fn do_file_op(host: &str, request: Request) -> Result<()> {
+    let mut conn = Connection::open(host)?;
+    if !conn.has_capability(CAP_FILE_OPS) {
+        return Err(io::Error::other("Server does not support file management"));
+    }
+    conn.expect_ok(&request)
+}
+
+enum Outcome {
+    Copied,
+    Skipped(String),
+    Failed(io::Error),
+}
+
+/// What happened to one file of a recursive copy, for the final summary.
+struct FileResult {
+    path: String,
+    outcome: Outcome,
+}
+
+impl FileResult {
+    fn new(path: String, result: Result<()>) -> FileResult {
+        let outcome = match result {
+            Ok(()) => Outcome::Copied,
+            Err(e) => Outcome::Failed(e),
+        };
+        FileResult { path, outcome }
+    }
+
+    fn skipped(path: String, reason: &str) -> FileResult {
+        FileResult { path, outcome: Outcome::Skipped(reason.to_string()) }
+    }
+}
+
+/// One file of a batch copy: settled already, or a transfer still to run.
+enum Job {
+    Done(FileResult),
+    Get(String, EndpointPaths),
+    Put(String, EndpointPaths),
+}
+
+/// How many transfers can run at once on `conn`'s server: several streams
+/// when it multiplexes, otherwise one after another.
+fn transfer_streams(conn: &Connection) -> usize {
+    if conn.has_capability(CAP_MUX) {
+        MAX_STREAMS
+    } else {
+        1
+    }
+}
+
+fn host_streams(host: &str) -> usize {
+    match Connection::open(host) {
+        Ok(conn) => {
+            let streams = transfer_streams(&conn);
+            conn.release();
+            streams
+        }
+        Err(_) => 1,
+    }
+}
+
+/// Runs the transfers among `jobs`, up to `streams` at a time, and returns
+/// the results in job order.
+fn run_jobs(jobs: Vec<Job>, streams: usize) -> Vec<FileResult> {
+    let queue = Mutex::new(jobs.into_iter().enumerate());
+    let (sender, finished) = channel();
+    thread::scope(|scope| {
+        for _ in 0..streams {
+            let (queue, sender) = (&queue, sender.clone());
+            scope.spawn(move || loop {
+                let Some((idx, job)) = queue.lock().unwrap().next() else {
+                    break;
+                };
+                let result = match job {
+                    Job::Done(result) => result,
+                    Job::Get(display, paths) => FileResult::new(display, try_get(paths)),
+                    Job::Put(display, paths) => FileResult::new(display, try_put(paths)),
+                };
+                let _ = sender.send((idx, result));
+            });
+        }
+    });
+    drop(sender);
+
+    let mut results: Vec<(usize, FileResult)> = finished.into_iter().collect();
+    results.sort_by_key(|(idx, _)| *idx);
+    results.into_iter().map(|(_, result)| result).collect()
+}
+
+/// Prints one line per file and the totals. Returns the exit code: 0 if
+/// nothing failed, `EXIT_PARTIAL` if only some files did, and otherwise
+/// the code of the first failure.
+fn print_summary(results: &[FileResult]) -> i32 {
+    let (mut copied, mut skipped, mut failed) = (0, 0, 0);
+    println!("Summary:");
+    for result in results {
+        match &result.outcome {
+            Outcome::Copied => {
+                copied += 1;
+                println!("  copied   {}", result.path);
+            }
+            Outcome::Skipped(reason) => {
+                skipped += 1;
+                println!("  skipped  {}: {}", result.path, reason);
+            }
+            Outcome::Failed(err) => {
+                failed += 1;
+                println!("  failed   {}: {}", result.path, err);
+            }
+        }
+    }
+    println!("{} copied, {} skipped, {} failed.", copied, skipped, failed);
+
+    if failed == 0 {
+        return 0;
+    }
+    if copied + skipped > 0 {
+        return EXIT_PARTIAL;
+    }
+    results
+        .iter()
+        .find_map(|result| match &result.outcome {
+            Outcome::Failed(e) => Some(exit_code(e)),
+            _ => None,
+        })
+        .unwrap_or(1)
+}
+
+/// The exit code that describes `err`.
+fn exit_code(err: &io::Error) -> i32 {
+    if let Some(err) = GetError::from_io(err) {
+        return match err {
+            GetError::ServerBusy(_) | GetError::LimitReached(_) => EXIT_BUSY,
+            GetError::IntegrityError(_) => EXIT_INTEGRITY,
+            GetError::NotFound(_)
+            | GetError::NotEmpty(_)
+            | GetError::PermissionDenied(_)
+            | GetError::AccessDenied(_)
+            | GetError::FileError(_) => EXIT_REMOTE,
+            _ => 1,
+        };
+    }
+    match err.kind() {
+        io::ErrorKind::ConnectionRefused
+        | io::ErrorKind::NotConnected
+        | io::ErrorKind::HostUnreachable
+        | io::ErrorKind::NetworkUnreachable
+        | io::ErrorKind::AddrNotAvailable
+        | io::ErrorKind::TimedOut
+        | io::ErrorKind::WouldBlock => EXIT_CONNECTION,
+        // The connection was there but dropped partway through
+        io::ErrorKind::ConnectionReset
+        | io::ErrorKind::ConnectionAborted
+        | io::ErrorKind::BrokenPipe
+        | io::ErrorKind::UnexpectedEof => EXIT_PARTIAL,
+        _ => 1,
+    }
+}
+
+/// Appends a `/`-separated relative path to a remote directory.
+fn join_remote(base: &OsStr, relative: &OsStr) -> OsString {
+    let base = os_str_bytes(base);
+    let base = base.strip_suffix(b"/").unwrap_or(&base);
+    if base.is_empty() || base == b"." {
+        return relative.to_os_string();
+    }
+    let mut joined = base.to_vec();
+    joined.push(b'/');
+    joined.extend_from_slice(&os_str_bytes(relative));
+    os_string_from_bytes(joined)
+}
+
+/// `relative`, a path the server sent, joined below `local_dir`. `None` when
+/// it is empty or has a root, `.` or `..` in it, so that a server cannot make
+/// the client write outside the destination.
+fn local_below(local_dir: &Path, relative: &OsStr) -> Option<PathBuf> {
+    let relative = Path::new(relative);
+    let mut components = relative.components().peekable();
+    let below = components.peek().is_some() && components.all(|c| matches!(c, Component::Normal(_)));
+    below.then(|| local_dir.join(relative))
+}
+
+/// A local relative path in the `/`-separated form used on the wire.
+fn remote_relative(relative: &Path) -> OsString {
+    let mut joined = Vec::new();
+    for component in relative.iter() {
+        if !joined.is_empty() {
+            joined.push(b'/');
+        }
+        joined.extend_from_slice(&os_str_bytes(component));
+    }
+    os_string_from_bytes(joined)
+}
+
+/// Collects the directories and files under `dir`, relative to the walk's
+/// root and parents first. Symlinks are not followed.
+fn walk_local(
+    dir: &Path,
+    prefix: &Path,
+    dirs: &mut Vec<PathBuf>,
+    files: &mut Vec<PathBuf>,
+    skipped: &mut Vec<PathBuf>,
+) -> Result<()> {
+    let mut entries: Vec<_> = std::fs::read_dir(dir)?.collect::<Result<_>>()?;
+    entries.sort_by_key(|entry| entry.file_name());
+
+    for entry in entries {
+        let relative = prefix.join(entry.file_name());
+        let file_type = entry.file_type()?;
+        if file_type.is_dir() {
+            dirs.push(relative.clone());
+            walk_local(&entry.path(), &relative, dirs, files, skipped)?;
+        } else if file_type.is_file() {
+            files.push(relative);
+        } else {
+            skipped.push(relative);
+        }
+    }
+    Ok(())
+}
+
+/// Uploads the tree under `local_dir` into `remote_dir`, creating every
+/// directory first so empty ones survive too.
+fn copy_tree_up(local_dir: &Path, host: &str, remote_dir: &OsStr) -> Result<Vec<FileResult>> {
+    let (mut dirs, mut files, mut skipped) = (Vec::new(), Vec::new(), Vec::new());
+    walk_local(local_dir, Path::new(""), &mut dirs, &mut files, &mut skipped)?;
+
+    let mut conn = Connection::open(host)?;
+    if !conn.has_capability(CAP_FILE_OPS) {
+        return Err(io::Error::other("Server does not support recursive copies"));
+    }
+    if !matches!(os_str_bytes(remote_dir).as_ref(), b"" | b"." | b"./") {
+        conn.expect_ok(&Request::Mkdir { path: remote_dir.to_os_string() })?;
+    }
+    for dir in &dirs {
+        conn.expect_ok(&Request::Mkdir { path: join_remote(remote_dir, &remote_relative(dir)) })?;
+    }
+    let streams = transfer_streams(&conn);
+    conn.release();
+
+    let mut jobs: Vec<Job> = skipped
+        .iter()
+        .map(|path| Job::Done(FileResult::skipped(path.display().to_string(), "not a regular file")))
+        .collect();
+    for relative in files {
+        let paths = EndpointPaths {
+            remote_host: host.to_string(),
+            remote_path: join_remote(remote_dir, &remote_relative(&relative)),
+            local_path: local_dir.join(&relative),
+        };
+        jobs.push(Job::Put(relative.display().to_string(), paths));
+    }
+    Ok(run_jobs(jobs, streams))
+}
+
+/// Downloads the tree under `remote_dir` into `local_dir`.
+fn copy_tree_down(host: &str, remote_dir: &OsStr, local_dir: &Path) -> Result<Vec<FileResult>> {
+    let mut conn = Connection::open(host)?;
+    if !conn.has_capability(CAP_STAT) || !conn.has_capability(CAP_LIST) {
+        return Err(io::Error::other("Server does not support recursive copies"));
+    }
+    if conn.stat(remote_dir)?.kind != FileKind::Dir {
+        return Err(GetError::FileError(format!("'{}' is not a directory", remote_dir.to_string_lossy())).into());
+    }
+    let mut entries = Vec::new();
+    conn.list(remote_dir, true, None, |path, stat| entries.push((path, stat)))?;
+    let streams = transfer_streams(&conn);
+    conn.release();
+
+    std::fs::create_dir_all(local_dir)?;
+    let mut jobs = Vec::new();
+    for (relative, stat) in entries {
+        let display = relative.to_string_lossy().into_owned();
+        let Some(local_path) = local_below(local_dir, &relative) else {
+            jobs.push(Job::Done(FileResult::new(display, Err(io::Error::other("Path leaves the destination directory")))));
+            continue;
+        };
+        match stat.kind {
+            // Listed parents first, so every directory exists before its files arrive
+            FileKind::Dir => {
+                if let Err(e) = std::fs::create_dir_all(&local_path) {
+                    jobs.push(Job::Done(FileResult::new(display, Err(e))));
+                }
+            }
+            FileKind::File => {
+                let paths = EndpointPaths {
+                    remote_host: host.to_string(),
+                    remote_path: join_remote(remote_dir, &relative),
+                    local_path,
+                };
+                jobs.push(Job::Get(display, paths));
+            }
+            FileKind::Symlink | FileKind::Other => jobs.push(Job::Done(FileResult::skipped(display, "not a regular file"))),
+        }
+    }
+    Ok(run_jobs(jobs, streams))
+}
+
+/// Expands wildcards in a local path component by component, in name order,
+/// the way a shell would. Paths that exist as written are not expanded.
+fn expand_local(pattern: &Path) -> Vec<PathBuf> {
+    if !is_glob(&os_str_bytes(pattern.as_os_str())) || pattern.exists() {
+        return vec![pattern.to_path_buf()];
+    }
+
+    let mut matches = vec![PathBuf::new()];
+    for component in pattern.components() {
+        let wildcard = os_str_bytes(component.as_os_str());
+        if !is_glob(&wildcard) {
+            matches.iter_mut().for_each(|base| base.push(component));
+            continue;
+        }
+
+        let mut next = Vec::new();
+        for base in &matches {
+            let dir = if base.as_os_str().is_empty() { Path::new(".") } else { base.as_path() };
+            let Ok(entries) = std::fs::read_dir(dir) else {
+                continue;
+            };
+            // Like shells, only match hidden files when the pattern asks for them
+            let mut names: Vec<OsString> = entries
+                .flatten()
+                .map(|entry| entry.file_name())
+                .filter(|name| {
+                    let name = os_str_bytes(name);
+                    (!name.starts_with(b".") || wildcard.starts_with(b".")) && glob_match(&wildcard, &name)
+                })
+                .collect();
+            names.sort();
+            next.extend(names.into_iter().map(|name| base.join(name)));
+        }
+        matches = next;
+    }
+    matches
+}
+
+/// Expands a remote glob on the server with `LIST`. A plain path is checked
+/// with `STAT` instead. Returns full remote paths.
+fn expand_remote(host: &str, remote_path: &OsStr) -> Result<Vec<(OsString, FileStat)>> {
+    let mut conn = Connection::open(host)?;
+    if !conn.has_capability(CAP_STAT) || !conn.has_capability(CAP_LIST) {
+        return Err(io::Error::other("Server does not support wildcards"));
+    }
+
+    let (dir, pattern) = split_pattern(remote_path);
+    let mut matches = Vec::new();
+    match pattern {
+        Some(pattern) => {
+            conn.list(&dir, false, Some(&pattern), |relative, stat| {
+                matches.push((join_remote(&dir, &relative), stat))
+            })?;
+        }
+        None => matches.push((remote_path.to_os_string(), conn.stat(remote_path)?)),
+    }
+    conn.release();
+    Ok(matches)
+}
+
+/// Last `/`-separated component of a remote path.
+fn remote_file_name(remote_path: &OsStr) -> OsString {
+    let bytes = os_str_bytes(remote_path);
+    let bytes = bytes.strip_suffix(b"/").unwrap_or(&bytes);
+    let start = bytes.iter().rposition(|&b| b == b'/').map_or(0, |idx| idx + 1);
+    os_string_from_bytes(bytes[start..].to_vec())
+}
+
+/// Tags the results of a tree copy with the directory they came from.
+fn prefixed(prefix: &str, results: Result<Vec<FileResult>>) -> Vec<FileResult> {
+    match results {
+        Ok(results) => results
+            .into_iter()
+            .map(|result| FileResult { path: format!("{}/{}", prefix, result.path), ..result })
+            .collect(),
+        Err(e) => vec![FileResult::new(prefix.to_string(), Err(e))],
+    }
+}
+
+/// Uploads every local source, after expanding wildcards, into the remote
+/// directory `remote_dir`.
+fn copy_many_up(sources: &[OsString], host: &str, remote_dir: &OsStr, recursive: bool) -> Vec<FileResult> {
+    let mut jobs = Vec::new();
+    for source in sources {
+        let pattern = normalize_path(source);
+        let matches = expand_local(&pattern);
+        if matches.is_empty() {
+            let pattern = pattern.display().to_string();
+            jobs.push(Job::Done(FileResult::new(pattern.clone(), Err(GetError::NotFound(pattern).into()))));
+        }
+
+        for local_path in matches {
+            let display = local_path.display().to_string();
+            let Some(name) = local_path.file_name() else {
+                jobs.push(Job::Done(FileResult::new(display, Err(io::Error::other("Source has no file name")))));
+                continue;
+            };
+            let remote_path = join_remote(remote_dir, name);
+
+            if local_path.is_dir() {
+                if recursive {
+                    let results = prefixed(&display, copy_tree_up(&local_path, host, &remote_path));
+                    jobs.extend(results.into_iter().map(Job::Done));
+                } else {
+                    jobs.push(Job::Done(FileResult::skipped(display, "is a directory (use -r)")));
+                }
+            } else {
+                let paths = EndpointPaths { remote_host: host.to_string(), remote_path, local_path };
+                jobs.push(Job::Put(display, paths));
+            }
+        }
+    }
+    run_jobs(jobs, host_streams(host))
+}
+
+/// Downloads every remote source, after the server expands its wildcards,
+/// into the local directory `local_dir`.
+fn copy_many_down(sources: &[EndpointPaths], local_dir: &Path, recursive: bool) -> Result<Vec<FileResult>> {
+    std::fs::create_dir_all(local_dir)?;
+
+    let mut jobs = Vec::new();
+    for source in sources {
+        let matches = match expand_remote(&source.remote_host, &source.remote_path) {
+            Ok(matches) => matches,
+            Err(e) => {
+                jobs.push(Job::Done(FileResult::new(source.remote_path.to_string_lossy().into_owned(), Err(e))));
+                continue;
+            }
+        };
+        if matches.is_empty() {
+            let pattern = source.remote_path.to_string_lossy().into_owned();
+            jobs.push(Job::Done(FileResult::new(pattern.clone(), Err(GetError::NotFound(pattern).into()))));
+        }
+
+        for (remote_path, stat) in matches {
+            let display = remote_path.to_string_lossy().into_owned();
+            let Some(local_path) = local_below(local_dir, &remote_file_name(&remote_path)) else {
+                jobs.push(Job::Done(FileResult::new(display, Err(io::Error::other("Path leaves the destination directory")))));
+                continue;
+            };
+            match stat.kind {
+                FileKind::Dir if recursive => {
+                    let results = prefixed(&display, copy_tree_down(&source.remote_host, &remote_path, &local_path));
+                    jobs.extend(results.into_iter().map(Job::Done));
+                }
+                FileKind::Dir => jobs.push(Job::Done(FileResult::skipped(display, "is a directory (use -r)"))),
+                FileKind::File => {
+                    let paths = EndpointPaths { remote_host: source.remote_host.clone(), remote_path, local_path };
+                    jobs.push(Job::Get(display, paths));
+                }
+                FileKind::Symlink | FileKind::Other => {
+                    jobs.push(Job::Done(FileResult::skipped(display, "not a regular file")))
+                }
+            }
+        }
+    }
+
+    // Sources may name different hosts; go only as wide as the narrowest
+    let mut hosts: Vec<&str> = sources.iter().map(|source| source.remote_host.as_str()).collect();
+    hosts.sort_unstable();
+    hosts.dedup();
+    let streams = hosts.into_iter().map(host_streams).min().unwrap_or(1);
+    Ok(run_jobs(jobs, streams))
+}
+
+/// Whether a copy has to go through the cp-style path: several sources,
+/// wildcards, or a destination that names a directory to copy into.
+fn is_multi_copy(sources: &[OsString], destination: &OsStr, recursive: bool) -> bool {
+    let has_glob = sources.iter().any(|source| {
+        if is_remote(source) {
+            is_glob(&os_str_bytes(&split_host_path(source, OsStr::new("")).remote_path))
+        } else {
+            is_glob(&os_str_bytes(source))
+        }
+    });
+    let names_dir = os_str_bytes(destination).ends_with(b"/")
+        || (!is_remote(destination) && Path::new(destination).is_dir());
+    sources.len() > 1 || has_glob || (!recursive && names_dir)
+}
+
+/// Ends every pooled session with `QUIT` and closes multiplexed links, so
+/// the server frees their slots.
+fn close_sessions() {
+    for mut connection in IDLE_SESSIONS.lock().unwrap().drain(..) {
+        if Request::Quit.encode(&mut connection.writer).is_ok() {
+            let _ = Response::decode(&mut connection.reader);
+        }
+    }
+    let links: Vec<Arc<Link>> = LINKS.lock().unwrap().drain(..).collect();
+    for link in links {
+        link.close();
+    }
+}
+
+/// Parses the value of `option`, exiting with a usage error when it is
+/// missing or `parse` rejects it.
+fn option_value<T>(value: Option<&OsString>, option: &str, parse: impl Fn(&str) -> Option<T>) -> T {
+    let Some(value) = value else {
+        eprintln!("Error: Missing value for {}", option);
+        process::exit(EXIT_USAGE);
+    };
+    match value.to_str().and_then(parse) {
+        Some(value) => value,
+        None => {
+            eprintln!("Error: Invalid value for {}", option);
+            process::exit(EXIT_USAGE);
+        }
+    }
+}
+
+/// A non-negative number of seconds, fractions allowed.
+fn parse_seconds(value: &str) -> Option<Duration> {
+    value.parse().ok().and_then(|secs| Duration::try_from_secs_f64(secs).ok())
+}
+
+fn print_usage(program: &OsStr) {
+    let program = program.to_string_lossy();
+    eprintln!("Usage: {} [--debug] [--port <port>] [retry options] [-r] <source> <destination>", program);
+    eprintln!("       {} [--debug] [-r] <source>... <directory>", program);
+    eprintln!("       {} [--debug] --stat [--json] <host:path>", program);
+    eprintln!("       {} [--debug] ls [-r] [--json] <host:dir[/pattern]>", program);
+    eprintln!("       {} [--debug] rm|rmdir|mkdir <host:path>", program);
+    eprintln!("       {} [--debug] mv <host:from> <[host:]to>", program);
+    eprintln!("Remote paths are [user@]host:[port:]path or remcp://[user@]host[:port]/path;");
+    eprintln!("IPv6 hosts go in brackets, as in [::1]:path. Write ./a:b for a local name with a colon.");
+    eprintln!("Retry options: --retries <n> (default 4), --retry-delay <secs> (default 1),");
+    eprintln!("               --retry-max-delay <secs> (default 30), --no-retry-jitter");
+    eprintln!("Timeouts: --connect-timeout <secs> (default 10), --chunk-timeout <secs> (default 30); 0 waits forever");
+}
+
 fn main() {
-    let args: Vec<String> = env::args().collect();
+    let args: Vec<OsString> = env::args_os().collect();
     let mut positional_args = vec![];
+    let mut stat = false;
+    let mut json = false;
+    let mut recursive = false;
 
-    for arg in args.iter().skip(1) {
+    let mut iter = args.iter().skip(1);
+    while let Some(arg) = iter.next() {
         if arg == "--debug" {
             unsafe { shared_lib::debug_utils::DEBUG_MODE = true };
             println!("Debug mode enabled.");
+        } else if arg == "--stat" {
+            stat = true;
+        } else if arg == "--json" {
+            json = true;
+        } else if arg == "-r" || arg == "--recursive" {
+            recursive = true;
+        } else if arg == "--port" {
+            let port = option_value(iter.next(), "--port", |value| value.parse().ok());
+            unsafe { DEFAULT_PORT = port };
+        } else if arg == "--retries" {
+            let retries = option_value(iter.next(), "--retries", |value| value.parse().ok());
+            unsafe { RETRIES = retries };
+        } else if arg == "--retry-delay" {
+            let delay = option_value(iter.next(), "--retry-delay", parse_seconds);
+            unsafe { RETRY_DELAY = delay };
+        } else if arg == "--retry-max-delay" {
+            let delay = option_value(iter.next(), "--retry-max-delay", parse_seconds);
+            unsafe { RETRY_MAX_DELAY = delay };
+        } else if arg == "--connect-timeout" {
+            let timeout = option_value(iter.next(), "--connect-timeout", parse_seconds);
+            unsafe { CONNECT_TIMEOUT = timeout };
+        } else if arg == "--chunk-timeout" {
+            let timeout = option_value(iter.next(), "--chunk-timeout", parse_seconds);
+            unsafe { CHUNK_TIMEOUT = timeout };
+        } else if arg == "--no-retry-jitter" {
+            unsafe { RETRY_JITTER = false };
         } else {
             positional_args.push(arg.clone());
         }
     }
 
-    if positional_args.len() != 2 {
-        eprintln!("Usage: {} [--debug] <source> <destination>", args[0]);
-        process::exit(1);
+    for arg in &positional_args {
+        if let Some(Err(e)) = parse_remote(arg) {
+            eprintln!("Error: Invalid remote path '{}': {}", arg.to_string_lossy(), e);
+            process::exit(EXIT_USAGE);
+        }
+    }
+
+    if stat {
+        if positional_args.len() != 1 || !is_remote(&positional_args[0]) {
+            print_usage(&args[0]);
+            process::exit(EXIT_USAGE);
+        }
+        let paths = split_host_path(&positional_args[0], OsStr::new(""));
+        if let Err(e) = do_stat(&paths, json) {
+            eprintln!("STAT operation failed: {}", e);
+            process::exit(exit_code(&e));
+        }
+        return;
+    }
+
+    let subcommand = positional_args.first().and_then(|arg| arg.to_str()).map(str::to_string);
+    match subcommand.as_deref() {
+        Some("ls") => {
+            if positional_args.len() != 2 || !is_remote(&positional_args[1]) {
+                print_usage(&args[0]);
+                process::exit(EXIT_USAGE);
+            }
+            let paths = split_host_path(&positional_args[1], OsStr::new(""));
+            if let Err(e) = do_ls(&paths, recursive, json) {
+                eprintln!("LIST operation failed: {}", e);
+                process::exit(exit_code(&e));
+            }
+            return;
+        }
+        Some(command @ ("rm" | "rmdir" | "mkdir")) => {
+            if positional_args.len() != 2 || !is_remote(&positional_args[1]) {
+                print_usage(&args[0]);
+                process::exit(EXIT_USAGE);
+            }
+            let paths = split_host_path(&positional_args[1], OsStr::new(""));
+            let path = paths.remote_path.clone();
+            let (request, done) = match command {
+                "rm" => (Request::Delete { path }, "Removed"),
+                "rmdir" => (Request::Rmdir { path }, "Removed directory"),
+                _ => (Request::Mkdir { path }, "Created directory"),
+            };
+            if let Err(e) = do_file_op(&paths.remote_host, request) {
+                eprintln!("{} failed: {}", command, e);
+                process::exit(exit_code(&e));
+            }
+            println!("{} '{}'.", done, paths.remote_path.to_string_lossy());
+            return;
+        }
+        Some("mv") => {
+            if positional_args.len() != 3 || !is_remote(&positional_args[1]) {
+                print_usage(&args[0]);
+                process::exit(EXIT_USAGE);
+            }
+            let from = split_host_path(&positional_args[1], OsStr::new(""));
+            // The target is on the same server, with or without the host prefix
+            let to = if is_remote(&positional_args[2]) {
+                let to = split_host_path(&positional_args[2], OsStr::new(""));
+                if to.remote_host != from.remote_host {
+                    eprintln!("Error: mv cannot move files between servers.");
+                    process::exit(EXIT_USAGE);
+                }
+                to.remote_path
+            } else {
+                positional_args[2].clone()
+            };
+            let request = Request::Rename { from: from.remote_path.clone(), to: to.clone() };
+            if let Err(e) = do_file_op(&from.remote_host, request) {
+                eprintln!("mv failed: {}", e);
+                process::exit(exit_code(&e));
+            }
+            println!("Renamed '{}' to '{}'.", from.remote_path.to_string_lossy(), to.to_string_lossy());
+            return;
+        }
+        _ => {}
+    }
+
+    if positional_args.len() < 2 {
+        print_usage(&args[0]);
+        process::exit(EXIT_USAGE);
+    }
+
+    let (sources, destination) = positional_args.split_at(positional_args.len() - 1);
+    let destination = &destination[0];
+    if is_multi_copy(sources, destination, recursive) {
+        let results = if is_remote(destination) {
+            if let Some(source) = sources.iter().find(|source| is_remote(source)) {
+                eprintln!("Error: Source '{}' and destination cannot both be remote.", source.to_string_lossy());
+                process::exit(EXIT_USAGE);
+            }
+            let target = split_host_path(destination, OsStr::new(""));
+            Ok(copy_many_up(sources, &target.remote_host, &target.remote_path, recursive))
+        } else {
+            if let Some(source) = sources.iter().find(|source| !is_remote(source)) {
+                eprintln!("Error: Source '{}' and destination cannot both be local.", source.to_string_lossy());
+                process::exit(EXIT_USAGE);
+            }
+            let sources: Vec<EndpointPaths> = sources.iter().map(|source| split_host_path(source, destination)).collect();
+            copy_many_down(&sources, &normalize_path(destination), recursive)
+        };
+        close_sessions();
+        match results {
+            Ok(results) => match print_summary(&results) {
+                0 => {}
+                code => process::exit(code),
+            },
+            Err(e) => {
+                eprintln!("Copy failed: {}", e);
+                process::exit(exit_code(&e));
+            }
+        }
+        return;
     }
 
     let src = positional_args[0].clone();
     let dst = positional_args[1].clone();
 
-    let is_src_remote = src.contains(":");
-    let is_dst_remote = dst.contains(":");
+    let is_src_remote = is_remote(&src);
+    let is_dst_remote = is_remote(&dst);
 
     if is_src_remote && is_dst_remote {
         eprintln!("Error: Both source and destination cannot be remote.");
-        process::exit(1);
+        process::exit(EXIT_USAGE);
     }
 
     if !is_src_remote && !is_dst_remote {
         eprintln!("Error: Both source and destination cannot be local.");
-        process::exit(1);
+        process::exit(EXIT_USAGE);
     }
 
     let paths = if is_src_remote {
@@ -353,17 +1581,42 @@ fn main() {
         split_host_path(&dst, &src)
     };
 
-    if is_src_remote {
-        if let Err(e) = try_get(paths) {
-            eprintln!("GET operation failed: {}", e);
+    if recursive {
+        let results = if is_src_remote {
+            copy_tree_down(&paths.remote_host, &paths.remote_path, &paths.local_path)
         } else {
-            println!("GET operation succeeded.");
+            copy_tree_up(&paths.local_path, &paths.remote_host, &paths.remote_path)
+        };
+        close_sessions();
+        match results {
+            Ok(results) => match print_summary(&results) {
+                0 => {}
+                code => process::exit(code),
+            },
+            Err(e) => {
+                eprintln!("Recursive copy failed: {}", e);
+                process::exit(exit_code(&e));
+            }
         }
+        return;
+    }
+
+    if !is_src_remote && paths.local_path.is_dir() {
+        eprintln!("Error: '{}' is a directory (use -r to copy it).", paths.local_path.display());
+        process::exit(EXIT_USAGE);
+    }
+
+    let (operation, result) = if is_src_remote {
+        ("GET", try_get(paths))
     } else {
-        if let Err(e) = try_put(paths) {
-            eprintln!("PUT operation failed: {}", e);
-        } else {
-            println!("PUT operation succeeded.");
+        ("PUT", try_put(paths))
+    };
+    close_sessions();
+    match result {
+        Ok(()) => println!("{} operation succeeded.", operation),
+        Err(e) => {
+            eprintln!("{} operation failed: {}", operation, e);
+            process::exit(exit_code(&e));
         }
     }
-}
\ No newline at end of file
+}
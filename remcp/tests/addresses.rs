@@ -0,0 +1,41 @@
+use std::fs::{create_dir_all, read, remove_dir_all, write};
+
+mod common;
+use common::{run_client, start_server};
+
+#[test]
+fn test_listen_addresses_and_ports() {
+    let work_dir = "addresses_test";
+    create_dir_all(format!("{}/served", work_dir)).expect("Failed to create work directory");
+    let local_file = format!("{}/local.txt", work_dir);
+    write(&local_file, b"reachable on any address").expect("Failed to write local file");
+
+    let served = format!("{}/served", work_dir);
+    let mut server = start_server(&["--root", &served, "--listen", "127.0.0.1:7979", "--listen", "[::1]:7979"]);
+
+    let put = run_client(&[&local_file, "127.0.0.1:7979:up.txt"]);
+    let via_flag = format!("{}/via_flag.txt", work_dir);
+    let get_flag = run_client(&["--port", "7979", "127.0.0.1:up.txt", &via_flag]);
+    let via_ipv6 = format!("{}/via_ipv6.txt", work_dir);
+    let get_ipv6 = run_client(&["[::1]:7979:up.txt", &via_ipv6]);
+    let ls_ipv6 = run_client(&["--port", "7979", "ls", "[::1]:."]);
+    let default_port = run_client(&["127.0.0.1:up.txt", &format!("{}/unreachable.txt", work_dir)]);
+
+    server.kill().ok();
+    server.wait().ok();
+
+    let uploaded = read(format!("{}/served/up.txt", work_dir)).unwrap_or_default();
+    let (flag_copy, ipv6_copy) = (read(&via_flag).unwrap_or_default(), read(&via_ipv6).unwrap_or_default());
+    remove_dir_all(work_dir).ok();
+
+    let original = b"reachable on any address".to_vec();
+    assert!(String::from_utf8_lossy(&put.stdout).contains("PUT operation succeeded"), "PUT to host:port:path failed");
+    assert_eq!(uploaded, original, "Upload content mismatch");
+    assert!(get_flag.status.success() && flag_copy == original, "GET with --port failed");
+    assert!(get_ipv6.status.success() && ipv6_copy == original, "GET over IPv6 failed");
+    assert!(String::from_utf8_lossy(&ls_ipv6.stdout).contains("up.txt"), "ls over IPv6 failed");
+    assert!(
+        String::from_utf8_lossy(&default_port.stderr).contains("GET operation failed"),
+        "Nothing listens on the default port, yet the GET did not fail"
+    );
+}
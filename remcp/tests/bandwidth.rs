@@ -3,7 +3,6 @@ use std::io::{BufRead, BufReader, Write};
 use std::thread::{self, sleep};
 use std::time::Duration;
 use std::fs::{File, remove_file};
-use std::path::Path;
 
 #[test]
 fn test_bandwidth_distribution() {
@@ -26,20 +25,16 @@ fn test_bandwidth_distribution() {
     let mut server_stdout_lines = Vec::new();
 
     let server_handle = thread::spawn(move || {
-        for line in server_stdout_reader.lines() {
-            if let Ok(l) = line {
-                println!("[SERVER STDOUT] {}", l);
-                server_stdout_lines.push(l);
-            }
+        for l in server_stdout_reader.lines().map_while(Result::ok) {
+            println!("[SERVER STDOUT] {}", l);
+            server_stdout_lines.push(l);
         }
         server_stdout_lines
     });
 
     thread::spawn(move || {
-        for line in server_stderr_reader.lines() {
-            if let Ok(line) = line {
-                eprintln!("[SERVER STDERR] {}", line);
-            }
+        for line in server_stderr_reader.lines().map_while(Result::ok) {
+            eprintln!("[SERVER STDERR] {}", line);
         }
     });
 
@@ -75,10 +70,8 @@ fn test_bandwidth_distribution() {
             let stdout_reader = BufReader::new(stdout);
             let cid = i;
             thread::spawn(move || {
-                for line in stdout_reader.lines() {
-                    if let Ok(line) = line {
-                        println!("[CLIENT {} STDOUT] {}", cid, line);
-                    }
+                for line in stdout_reader.lines().map_while(Result::ok) {
+                    println!("[CLIENT {} STDOUT] {}", cid, line);
                 }
             });
         }
@@ -87,10 +80,8 @@ fn test_bandwidth_distribution() {
             let cid = i;
             let stderr_reader = BufReader::new(stderr);
             thread::spawn(move || {
-                for line in stderr_reader.lines() {
-                    if let Ok(line) = line {
-                        eprintln!("[CLIENT {} STDERR] {}", cid, line);
-                    }
+                for line in stderr_reader.lines().map_while(Result::ok) {
+                    eprintln!("[CLIENT {} STDERR] {}", cid, line);
                 }
             });
         }
@@ -109,7 +100,7 @@ fn test_bandwidth_distribution() {
 
     let next_lines: Vec<String> = server_lines.iter()
         .filter(|l| l.contains("NEXT "))
-        .map(|l| l.clone())
+        .cloned()
         .collect();
 
     let mut chunk_sizes = Vec::new();
@@ -132,10 +123,10 @@ fn test_bandwidth_distribution() {
     );
 
     for i in 0..file_count {
-        let absolute_remote_file_path = format!("{}\\{}", cwd.display(), remote_file_names[i]);
-        assert!(Path::new(&absolute_remote_file_path).exists(),
+        let absolute_remote_file_path = cwd.join(&remote_file_names[i]);
+        assert!(absolute_remote_file_path.exists(),
             "Uploaded file does not exist on the server: {}",
-            absolute_remote_file_path
+            absolute_remote_file_path.display()
         );
 
         let original_content = std::fs::read_to_string(&file_names[i]).expect("Failed to read original file");
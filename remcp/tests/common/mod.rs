@@ -0,0 +1,60 @@
+//! Helpers shared by the client integration tests. Each test file uses only
+//! some of them.
+#![allow(dead_code)]
+
+use std::io::{BufRead, BufReader};
+use std::process::{Child, Command, Output, Stdio};
+use std::thread;
+use std::time::Duration;
+
+/// Prints a child's output as it comes, each line tagged with `label`.
+pub fn forward_output(child: &mut Child, label: &'static str) {
+    if let Some(stdout) = child.stdout.take() {
+        let stdout_reader = BufReader::new(stdout);
+        thread::spawn(move || {
+            for line in stdout_reader.lines().map_while(Result::ok) {
+                println!("[{} STDOUT] {}", label, line);
+            }
+        });
+    }
+
+    if let Some(stderr) = child.stderr.take() {
+        let stderr_reader = BufReader::new(stderr);
+        thread::spawn(move || {
+            for line in stderr_reader.lines().map_while(Result::ok) {
+                eprintln!("[{} STDERR] {}", label, line);
+            }
+        });
+    }
+}
+
+/// Runs the client to completion and prints what it said.
+pub fn run_client(args: &[&str]) -> Output {
+    let output = Command::new("../target/debug/remcp")
+        .args(args)
+        .output()
+        .expect("Failed to run client");
+    println!("[CLIENT STDOUT] {}", String::from_utf8_lossy(&output.stdout));
+    eprintln!("[CLIENT STDERR] {}", String::from_utf8_lossy(&output.stderr));
+    output
+}
+
+/// Starts a server with `args`, its output tagged with `label`, without
+/// waiting for it to listen.
+pub fn spawn_server(args: &[&str], label: &'static str) -> Child {
+    let mut server = Command::new("../target/debug/remcp-serv")
+        .args(args)
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .expect("Failed to start server");
+    forward_output(&mut server, label);
+    server
+}
+
+/// Starts a server with `args` and gives it time to listen.
+pub fn start_server(args: &[&str]) -> Child {
+    let server = spawn_server(args, "SERVER");
+    thread::sleep(Duration::from_secs(2));
+    server
+}
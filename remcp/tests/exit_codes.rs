@@ -0,0 +1,68 @@
+use std::io::{BufRead, BufReader, Write};
+use std::net::TcpListener;
+use std::thread;
+use std::time::Duration;
+use std::fs::{create_dir_all, remove_dir_all, write};
+
+mod common;
+use common::{run_client, spawn_server};
+
+/// A server that negotiates checksums and then always sends a corrupt chunk.
+fn spawn_corrupting_server(port: u16) {
+    let listener = TcpListener::bind(("127.0.0.1", port)).expect("Failed to bind corrupting server");
+    thread::spawn(move || {
+        for stream in listener.incoming().map_while(Result::ok) {
+            let mut reader = BufReader::new(stream.try_clone().unwrap());
+            let mut writer = stream;
+            let mut line = String::new();
+            if reader.read_line(&mut line).is_err() {
+                continue;
+            }
+            let _ = writer.write_all(b"HELLO 1 checksums\n");
+            line.clear();
+            if reader.read_line(&mut line).is_ok() {
+                let _ = writer.write_all(b"OK 5\nNEXT 5 00000000\nhello");
+            }
+        }
+    });
+}
+
+#[test]
+fn test_exit_codes() {
+    let work_dir = "exit_codes_test";
+    create_dir_all(work_dir).expect("Failed to create work directory");
+    let local_file = format!("{}/present.txt", work_dir);
+    write(&local_file, b"present").expect("Failed to write local file");
+    let missing_file = format!("{}/absent.txt", work_dir);
+
+    let mut server = spawn_server(&[], "SERVER");
+    let mut busy_server = spawn_server(&["--max-clients", "0", "--listen", "127.0.0.1:7994"], "BUSY SERVER");
+
+    spawn_corrupting_server(7995);
+    thread::sleep(Duration::from_secs(2));
+
+    let copied = format!("{}/copied.txt", work_dir);
+    let success = run_client(&[&local_file, &format!("127.0.0.1:{}/uploaded.txt", work_dir)]);
+    let no_args = run_client(&[]);
+    let both_local = run_client(&[&local_file, &copied]);
+    let refused = run_client(&["--retries", "0", "--port", "7993", "127.0.0.1:anything.txt", &copied]);
+    let busy = run_client(&["--retries", "1", "--retry-max-delay", "0.2", "127.0.0.1:7994:anything.txt", &copied]);
+    let remote_missing = run_client(&[&format!("127.0.0.1:{}/no such file.txt", work_dir), &copied]);
+    let corrupt = run_client(&["127.0.0.1:7995:anything.txt", &copied]);
+    let partial = run_client(&[&local_file, &missing_file, &format!("127.0.0.1:{}/batch/", work_dir)]);
+
+    server.kill().ok();
+    server.wait().ok();
+    busy_server.kill().ok();
+    busy_server.wait().ok();
+    remove_dir_all(work_dir).ok();
+
+    assert_eq!(success.status.code(), Some(0), "Successful copy");
+    assert_eq!(no_args.status.code(), Some(2), "Missing arguments");
+    assert_eq!(both_local.status.code(), Some(2), "Two local paths");
+    assert_eq!(refused.status.code(), Some(3), "Nothing listening");
+    assert_eq!(busy.status.code(), Some(4), "Server busy after retries");
+    assert_eq!(remote_missing.status.code(), Some(5), "Missing remote file");
+    assert_eq!(corrupt.status.code(), Some(6), "Corrupt chunks on every attempt");
+    assert_eq!(partial.status.code(), Some(7), "One of two sources missing");
+}
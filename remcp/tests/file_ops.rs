@@ -0,0 +1,49 @@
+use std::fs::{create_dir_all, remove_dir_all, write};
+use std::path::Path;
+
+mod common;
+use common::{run_client, start_server};
+
+#[test]
+fn test_file_management() {
+    let work_dir = "file_ops_test";
+    create_dir_all(format!("{}/full", work_dir)).expect("Failed to create work directory");
+    write(format!("{}/full/keep.txt", work_dir), "keep").expect("Failed to write test file");
+    write(format!("{}/draft.txt", work_dir), "draft").expect("Failed to write test file");
+
+    let mut server = start_server(&[]);
+
+    let remote = |path: &str| format!("127.0.0.1:{}/{}", work_dir, path);
+    let mkdir = run_client(&["mkdir", &remote("new dir/nested")]);
+    let mv = run_client(&["mv", &remote("draft.txt"), &remote("new dir/final.txt")]);
+    let mv_missing = run_client(&["mv", &remote("draft.txt"), &remote("again.txt")]);
+    let rmdir_full = run_client(&["rmdir", &remote("full")]);
+    let rm = run_client(&["rm", &remote("full/keep.txt")]);
+    let rmdir = run_client(&["rmdir", &remote("full")]);
+    let rm_escape = run_client(&["rm", "127.0.0.1:../Cargo.toml"]);
+
+    server.kill().ok();
+    server.wait().ok();
+
+    let work = Path::new(work_dir);
+    let nested_exists = work.join("new dir/nested").is_dir();
+    let moved_exists = work.join("new dir/final.txt").is_file();
+    let draft_exists = work.join("draft.txt").exists();
+    let full_exists = work.join("full").exists();
+    remove_dir_all(work_dir).ok();
+
+    assert!(mkdir.status.success() && nested_exists, "MKDIR failed");
+    assert!(mv.status.success() && moved_exists && !draft_exists, "RENAME failed");
+
+    assert!(!mv_missing.status.success(), "Renaming a missing file succeeded");
+    assert!(String::from_utf8_lossy(&mv_missing.stderr).contains("Not found: "));
+
+    assert!(!rmdir_full.status.success(), "Removing a non-empty directory succeeded");
+    assert!(String::from_utf8_lossy(&rmdir_full.stderr).contains("Directory not empty: "));
+
+    assert!(rm.status.success() && rmdir.status.success() && !full_exists, "DELETE/RMDIR failed");
+
+    assert!(!rm_escape.status.success(), "Deleting outside the root succeeded");
+    assert!(String::from_utf8_lossy(&rm_escape.stderr).contains("Access denied"));
+    assert!(Path::new("Cargo.toml").exists());
+}
@@ -0,0 +1,38 @@
+use std::path::Path;
+use std::fs::{create_dir_all, read, remove_dir_all, write};
+
+mod common;
+use common::{run_client, start_server};
+
+#[test]
+fn test_corrupted_part_is_downloaded_again() {
+    let work_dir = "integrity_test";
+    let served = format!("{}/served", work_dir);
+    create_dir_all(&served).expect("Failed to create served directory");
+    let content: Vec<u8> = (0..3000).map(|i| (i % 251) as u8).collect();
+    write(format!("{}/data.bin", served), &content).expect("Failed to write served file");
+
+    // Written after the remote file, as a real partial download would be,
+    // but with the wrong bytes in it
+    let local = format!("{}/data.bin", work_dir);
+    let part = format!("{}/data.bin.part", work_dir);
+    write(&part, vec![0xAAu8; 1000]).expect("Failed to seed partial file");
+
+    let mut server = start_server(&["--root", &served, "--listen", "127.0.0.1:8003", "--transfer-rate", "0"]);
+
+    let output = run_client(&["127.0.0.1:8003:data.bin", &local]);
+    let downloaded = read(&local).unwrap_or_default();
+    let part_left = Path::new(&part).exists();
+
+    server.kill().ok();
+    server.wait().ok();
+    remove_dir_all(work_dir).ok();
+
+    assert_eq!(output.status.code(), Some(0), "The download should succeed on the next attempt");
+    assert!(
+        String::from_utf8_lossy(&output.stderr).contains("File digest mismatch. Discarding"),
+        "The corrupted partial file should be detected"
+    );
+    assert!(downloaded == content, "Downloaded content differs from the served file");
+    assert!(!part_left, "No partial file should be left behind");
+}
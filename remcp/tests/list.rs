@@ -0,0 +1,48 @@
+use std::fs::{create_dir_all, remove_dir_all, write};
+
+mod common;
+use common::{run_client, start_server};
+
+#[test]
+fn test_list_directory() {
+    let work_dir = "list_test";
+    create_dir_all(format!("{}/docs/deep", work_dir)).expect("Failed to create work directory");
+    for (path, content) in [
+        ("a.txt", "a"),
+        ("b.c", "bb"),
+        ("docs/readme.txt", "readme"),
+        ("docs/deep/more.txt", "more"),
+        ("docs/.upload.bin.remcp-part", "partial"),
+    ] {
+        write(format!("{}/{}", work_dir, path), content).expect("Failed to write test file");
+    }
+
+    let mut server = start_server(&[]);
+
+    let remote = format!("127.0.0.1:{}", work_dir);
+    let flat = run_client(&["ls", &remote]);
+    let recursive = run_client(&["ls", "-r", "--json", &remote]);
+    let filtered = run_client(&["ls", "-r", &format!("{}/*.txt", remote)]);
+    let missing = run_client(&["ls", &format!("{}/missing", remote)]);
+
+    server.kill().ok();
+    server.wait().ok();
+    remove_dir_all(work_dir).ok();
+
+    let flat = String::from_utf8_lossy(&flat.stdout);
+    let flat_names: Vec<&str> = flat.lines().filter_map(|line| line.split_whitespace().last()).collect();
+    assert_eq!(flat_names, vec!["a.txt", "b.c", "docs/"]);
+    assert!(flat.lines().any(|line| line.starts_with("-rw") && line.contains(" 2 ")), "Missing size in: {}", flat);
+
+    let recursive = String::from_utf8_lossy(&recursive.stdout);
+    for path in ["a.txt", "b.c", "docs", "docs/deep", "docs/deep/more.txt", "docs/readme.txt"] {
+        assert!(recursive.contains(&format!("\"path\":\"{}\"", path)), "Missing '{}' in: {}", path, recursive);
+    }
+    assert!(!recursive.contains("remcp-part"), "Partial upload listed: {}", recursive);
+
+    let filtered = String::from_utf8_lossy(&filtered.stdout);
+    let filtered_names: Vec<&str> = filtered.lines().filter_map(|line| line.split_whitespace().last()).collect();
+    assert_eq!(filtered_names, vec!["a.txt", "docs/deep/more.txt", "docs/readme.txt"]);
+
+    assert!(!missing.status.success(), "Listing a missing directory succeeded");
+}
@@ -0,0 +1,63 @@
+use std::fs::{create_dir_all, read_to_string, remove_dir_all, write};
+use std::path::Path;
+
+mod common;
+use common::{run_client, start_server};
+
+#[test]
+fn test_multiple_sources_and_globs() {
+    let work_dir = Path::new("multi_test");
+    create_dir_all(work_dir.join("local/logs")).expect("Failed to create work directory");
+    create_dir_all(work_dir.join("remote/archive")).expect("Failed to create work directory");
+    for (path, content) in [
+        ("local/a.bin", "a"),
+        ("local/b.bin", "b"),
+        ("local/logs/x.log", "x"),
+        ("local/logs/y.log", "y"),
+        ("local/logs/skip.txt", "skip"),
+        ("remote/archive/1.gz", "one"),
+        ("remote/archive/2.gz", "two"),
+        ("remote/archive/notes.txt", "notes"),
+    ] {
+        write(work_dir.join(path), content).expect("Failed to write test file");
+    }
+
+    let mut server = start_server(&["--transfer-rate", "65536"]);
+
+    let upload = run_client(&[
+        "multi_test/local/a.bin",
+        "multi_test/local/b.bin",
+        "multi_test/local/logs/*.log",
+        "127.0.0.1:multi_test/uploaded/",
+    ]);
+    let download = run_client(&["127.0.0.1:multi_test/remote/archive/*.gz", "multi_test/downloaded/"]);
+    let partial_failure = run_client(&[
+        "127.0.0.1:multi_test/remote/archive/notes.txt",
+        "127.0.0.1:multi_test/remote/archive/missing.txt",
+        "multi_test/partial/",
+    ]);
+
+    server.kill().ok();
+    server.wait().ok();
+
+    let read = |path: &str| read_to_string(work_dir.join(path)).unwrap_or_default();
+    let uploaded = ["a.bin", "b.bin", "x.log", "y.log"].map(|name| read(&format!("uploaded/{}", name)));
+    let skipped_txt = work_dir.join("uploaded/skip.txt").exists();
+    let downloaded = [read("downloaded/1.gz"), read("downloaded/2.gz")];
+    let notes_downloaded = work_dir.join("downloaded/notes.txt").exists();
+    let partial_notes = read("partial/notes.txt");
+    remove_dir_all(work_dir).ok();
+
+    assert!(upload.status.success(), "Multi-source upload failed");
+    assert_eq!(uploaded, ["a", "b", "x", "y"].map(String::from));
+    assert!(!skipped_txt, "File not matching the glob was uploaded");
+
+    assert!(download.status.success(), "Remote glob download failed");
+    assert_eq!(downloaded, ["one".to_string(), "two".to_string()]);
+    assert!(!notes_downloaded, "File not matching the remote glob was downloaded");
+
+    assert!(!partial_failure.status.success(), "A missing source did not fail the run");
+    assert_eq!(partial_notes, "notes", "Remaining sources were not copied");
+    let summary = String::from_utf8_lossy(&partial_failure.stdout);
+    assert!(summary.contains("1 copied, 0 skipped, 1 failed."), "Wrong totals in: {}", summary);
+}
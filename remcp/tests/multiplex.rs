@@ -0,0 +1,71 @@
+use std::process::{Command, Stdio};
+use std::io::{BufRead, BufReader};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+use std::fs::{create_dir_all, read, remove_dir_all, write};
+use std::path::Path;
+
+#[test]
+fn test_concurrent_download_over_one_connection() {
+    let work_dir = Path::new("multiplex_test");
+    let tree = work_dir.join("tree");
+    create_dir_all(tree.join("sub")).expect("Failed to create tree");
+
+    let files: Vec<String> = (0..8).map(|i| format!("{}file{}.bin", if i % 2 == 0 { "sub/" } else { "" }, i)).collect();
+    for (i, file) in files.iter().enumerate() {
+        let content: Vec<u8> = (0..300 + i as u32 * 50).map(|b| (b * 7 % 251) as u8).collect();
+        write(tree.join(file), content).expect("Failed to write tree file");
+    }
+
+    let mut server = Command::new("../target/debug/remcp-serv")
+        .arg("--transfer-rate")
+        .arg("4096")
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .expect("Failed to start server");
+
+    let connections = Arc::new(Mutex::new(0));
+    if let Some(stdout) = server.stdout.take() {
+        let connections = Arc::clone(&connections);
+        thread::spawn(move || {
+            for line in BufReader::new(stdout).lines().map_while(Result::ok) {
+                println!("[SERVER STDOUT] {}", line);
+                if line.starts_with("Client connected") {
+                    *connections.lock().unwrap() += 1;
+                }
+            }
+        });
+    }
+
+    thread::sleep(Duration::from_secs(2));
+
+    let download_dir = work_dir.join("downloaded");
+    let download = Command::new("../target/debug/remcp")
+        .args(["-r", "127.0.0.1:multiplex_test/tree", download_dir.to_str().unwrap()])
+        .output()
+        .expect("Failed to run client");
+    println!("[CLIENT STDOUT] {}", String::from_utf8_lossy(&download.stdout));
+    thread::sleep(Duration::from_millis(500));
+
+    server.kill().ok();
+    server.wait().ok();
+
+    let mismatches: Vec<&String> = files
+        .iter()
+        .filter(|file| read(tree.join(file)).ok() != read(download_dir.join(file)).ok())
+        .collect();
+    remove_dir_all(work_dir).ok();
+
+    assert!(download.status.success(), "Recursive download failed");
+    assert!(mismatches.is_empty(), "Files differ after download: {:?}", mismatches);
+    assert_eq!(*connections.lock().unwrap(), 1, "Download used more than one connection");
+
+    // Transfers finish in any order, but the summary keeps listing order
+    let summary = String::from_utf8_lossy(&download.stdout);
+    let first = summary.find("copied   file1.bin").expect("Missing file1.bin in summary");
+    let last = summary.find("copied   sub/file6.bin").expect("Missing sub/file6.bin in summary");
+    assert!(first < last, "Summary is out of order: {}", summary);
+    assert!(summary.contains("8 copied, 0 skipped, 0 failed."), "Wrong totals in: {}", summary);
+}
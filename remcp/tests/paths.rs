@@ -0,0 +1,43 @@
+use std::io::Write;
+use std::fs::{File, remove_dir_all, remove_file};
+
+mod common;
+use common::{run_client, start_server};
+
+#[test]
+fn test_paths_with_spaces() {
+    let cwd = std::env::current_dir().expect("Failed to get current directory");
+
+    let mut server = start_server(&[]);
+
+    let test_file_path = "test upload ç.txt";
+    {
+        let mut f = File::create(test_file_path).expect("Failed to create test file");
+        writeln!(f, "This file travels through a path with spaces").expect("Failed to write test file");
+    }
+
+    let remote_dir = "Área de Trabalho";
+    let remote_file_path = format!("{}/remote file.txt", remote_dir);
+    let put_ok = run_client(&[test_file_path, &format!("127.0.0.1:{}", remote_file_path)]).status.success();
+
+    let uploaded = cwd.join(&remote_file_path);
+    let uploaded_exists = uploaded.exists();
+
+    let downloaded_file = "test download ç.txt";
+    let get_ok = run_client(&[&format!("127.0.0.1:{}", remote_file_path), downloaded_file]).status.success();
+
+    server.kill().ok();
+    server.wait().ok();
+
+    let original_content = std::fs::read_to_string(test_file_path).expect("Failed to read original file");
+    let downloaded_content = std::fs::read_to_string(downloaded_file).unwrap_or_default();
+
+    remove_file(test_file_path).ok();
+    remove_file(downloaded_file).ok();
+    remove_dir_all(remote_dir).ok();
+
+    assert!(put_ok, "Client PUT failed");
+    assert!(uploaded_exists, "Uploaded file does not exist on the server: {}", uploaded.display());
+    assert!(get_ok, "Client GET failed");
+    assert_eq!(original_content, downloaded_content, "Content mismatch after GET");
+}
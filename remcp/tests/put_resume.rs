@@ -0,0 +1,54 @@
+use std::fs::{read, read_dir, remove_dir_all, write};
+
+mod common;
+use common::{run_client, start_server};
+
+#[test]
+fn test_put_resumes_from_server_partial() {
+    let work_dir = "put_resume_test";
+    std::fs::create_dir_all(work_dir).expect("Failed to create work directory");
+
+    let mut server = start_server(&["--transfer-rate", "4096"]);
+
+    let content: Vec<u8> = (0..10_000u32).map(|i| (i % 251) as u8).collect();
+    let local_file = format!("{}/local.bin", work_dir);
+    write(&local_file, &content).expect("Failed to write local file");
+
+    // The server already holds the first half of this upload
+    let matching = format!("{}/matching.bin", work_dir);
+    write(format!("{}/.matching.bin.remcp-part", work_dir), &content[..5_000]).expect("Failed to write partial");
+
+    // A partial whose bytes belong to some other file
+    let stale = format!("{}/stale.bin", work_dir);
+    write(format!("{}/.stale.bin.remcp-part", work_dir), vec![0xAAu8; 5_000]).expect("Failed to write stale partial");
+
+    // An older, longer file being replaced by the upload
+    let longer = format!("{}/longer.bin", work_dir);
+    write(&longer, vec![0x55u8; 20_000]).expect("Failed to write longer file");
+
+    let results: Vec<(String, bool)> = [&matching, &stale, &longer]
+        .iter()
+        .map(|remote| {
+            let ok = run_client(&[&local_file, &format!("127.0.0.1:{}", remote)]).status.success();
+            (remote.to_string(), ok)
+        })
+        .collect();
+
+    server.kill().ok();
+    server.wait().ok();
+
+    let uploaded: Vec<Vec<u8>> = results.iter().map(|(remote, _)| read(remote).unwrap_or_default()).collect();
+    let leftovers: Vec<String> = read_dir(work_dir)
+        .expect("Failed to list work directory")
+        .flatten()
+        .map(|entry| entry.file_name().to_string_lossy().into_owned())
+        .filter(|name| name.ends_with(".remcp-part"))
+        .collect();
+    remove_dir_all(work_dir).ok();
+
+    for ((remote, ok), data) in results.iter().zip(uploaded) {
+        assert!(ok, "Client PUT to '{}' failed", remote);
+        assert!(data == content, "Content mismatch in '{}' after PUT", remote);
+    }
+    assert!(leftovers.is_empty(), "Partial uploads left behind: {:?}", leftovers);
+}
@@ -0,0 +1,89 @@
+use std::fs::{create_dir_all, read, remove_dir_all, write};
+use std::path::Path;
+
+mod common;
+use common::{run_client, start_server};
+
+#[test]
+fn test_recursive_round_trip() {
+    let work_dir = Path::new("recursive_test");
+    let tree = work_dir.join("tree");
+    create_dir_all(tree.join("sub/deep")).expect("Failed to create tree");
+    create_dir_all(tree.join("empty")).expect("Failed to create tree");
+
+    let files = ["a.txt", "sub/b file.txt", "sub/deep/c.bin"];
+    for (i, file) in files.iter().enumerate() {
+        let content: Vec<u8> = (0..(i as u32 + 1) * 700).map(|b| (b % 251) as u8).collect();
+        write(tree.join(file), content).expect("Failed to write tree file");
+    }
+
+    let mut server = start_server(&["--transfer-rate", "65536"]);
+
+    let tree_arg = tree.to_str().unwrap();
+    let remote = "127.0.0.1:recursive_test/uploaded";
+    let download_dir = work_dir.join("downloaded");
+    let upload = run_client(&["-r", tree_arg, remote]);
+    let download = run_client(&["-r", remote, download_dir.to_str().unwrap()]);
+    let without_flag = run_client(&[tree_arg, remote]);
+
+    server.kill().ok();
+    server.wait().ok();
+
+    let uploaded_empty = work_dir.join("uploaded/empty").is_dir();
+    let downloaded_empty = download_dir.join("empty").is_dir();
+    let mismatches: Vec<&str> = files
+        .iter()
+        .copied()
+        .filter(|file| {
+            let original = read(tree.join(file)).ok();
+            original.is_none()
+                || read(work_dir.join("uploaded").join(file)).ok() != original
+                || read(download_dir.join(file)).ok() != original
+        })
+        .collect();
+    remove_dir_all(work_dir).ok();
+
+    assert!(upload.status.success(), "Recursive upload failed");
+    assert!(download.status.success(), "Recursive download failed");
+    assert!(mismatches.is_empty(), "Files differ after the round trip: {:?}", mismatches);
+    assert!(uploaded_empty && downloaded_empty, "Empty directory was not recreated");
+
+    let summary = String::from_utf8_lossy(&download.stdout);
+    assert!(summary.contains("copied   sub/b file.txt"), "Missing per-file line in: {}", summary);
+    assert!(summary.contains("3 copied, 0 skipped, 0 failed."), "Wrong totals in: {}", summary);
+
+    assert!(!without_flag.status.success(), "Copying a directory without -r succeeded");
+}
+
+#[test]
+fn test_same_stem_files_download_side_by_side() {
+    let work_dir = Path::new("same_stem_test");
+    let tree = work_dir.join("tree");
+    create_dir_all(&tree).expect("Failed to create tree");
+
+    // Same stem, so a part file named by replacing the extension would be shared
+    let files = ["a.txt", "a.bin", "a"];
+    for (i, file) in files.iter().enumerate() {
+        let content: Vec<u8> = (0..100_000u32).map(|b| ((b + i as u32 * 7) % 251) as u8).collect();
+        write(tree.join(file), content).expect("Failed to write tree file");
+    }
+
+    let root = work_dir.to_str().unwrap();
+    let mut server = start_server(&["--root", root, "--listen", "127.0.0.1:8007", "--transfer-rate", "262144"]);
+
+    let download_dir = work_dir.join("downloaded");
+    let download = run_client(&["-r", "127.0.0.1:8007:tree", download_dir.to_str().unwrap()]);
+
+    server.kill().ok();
+    server.wait().ok();
+
+    let mismatches: Vec<&str> = files
+        .iter()
+        .copied()
+        .filter(|file| read(tree.join(file)).ok() != read(download_dir.join(file)).ok())
+        .collect();
+    remove_dir_all(work_dir).ok();
+
+    assert!(download.status.success(), "Recursive download failed");
+    assert!(mismatches.is_empty(), "Files differ after the download: {:?}", mismatches);
+}
@@ -19,10 +19,8 @@ fn test_put_and_get() {
     if let Some(stdout) = server.stdout.take() {
         let stdout_reader = BufReader::new(stdout);
         thread::spawn(move || {
-            for line in stdout_reader.lines() {
-                if let Ok(line) = line {
-                    println!("[SERVER STDOUT] {}", line);
-                }
+            for line in stdout_reader.lines().map_while(Result::ok) {
+                println!("[SERVER STDOUT] {}", line);
             }
         });
     }
@@ -30,10 +28,8 @@ fn test_put_and_get() {
     if let Some(stderr) = server.stderr.take() {
         let stderr_reader = BufReader::new(stderr);
         thread::spawn(move || {
-            for line in stderr_reader.lines() {
-                if let Ok(line) = line {
-                    eprintln!("[SERVER STDERR] {}", line);
-                }
+            for line in stderr_reader.lines().map_while(Result::ok) {
+                eprintln!("[SERVER STDERR] {}", line);
             }
         });
     }
@@ -46,7 +42,7 @@ fn test_put_and_get() {
         writeln!(f, "This is a test file for upload").expect("Failed to write test file");
     }
 
-    let remote_file_path = r"storage\test_upload_remote.txt";
+    let remote_file_path = "storage/test_upload_remote.txt";
 
     let mut client = Command::new("../target/debug/remcp")
         .arg(test_file_path)
@@ -59,10 +55,8 @@ fn test_put_and_get() {
     if let Some(stdout) = client.stdout.take() {
         let stdout_reader = BufReader::new(stdout);
         thread::spawn(move || {
-            for line in stdout_reader.lines() {
-                if let Ok(line) = line {
-                    println!("[CLIENT STDOUT] {}", line);
-                }
+            for line in stdout_reader.lines().map_while(Result::ok) {
+                println!("[CLIENT STDOUT] {}", line);
             }
         });
     }
@@ -70,10 +64,8 @@ fn test_put_and_get() {
     if let Some(stderr) = client.stderr.take() {
         let stderr_reader = BufReader::new(stderr);
         thread::spawn(move || {
-            for line in stderr_reader.lines() {
-                if let Ok(line) = line {
-                    eprintln!("[CLIENT STDERR] {}", line);
-                }
+            for line in stderr_reader.lines().map_while(Result::ok) {
+                eprintln!("[CLIENT STDERR] {}", line);
             }
         });
     }
@@ -81,11 +73,11 @@ fn test_put_and_get() {
     let status = client.wait().expect("Failed to wait on client");
     assert!(status.success(), "Client PUT failed");
 
-    let absolute_remote_file_path = r"C:\Users\gabri\OneDrive\Área de Trabalho\odo\remcp_project\remcp\storage\test_upload_remote.txt";
+    let absolute_remote_file_path = cwd.join(remote_file_path);
     assert!(
-        Path::new(absolute_remote_file_path).exists(),
+        absolute_remote_file_path.exists(),
         "Uploaded file does not exist on the server: {}",
-        absolute_remote_file_path
+        absolute_remote_file_path.display()
     );
 
     let downloaded_file = "test_download.txt";
@@ -104,10 +96,8 @@ fn test_put_and_get() {
     if let Some(stdout) = client2.stdout.take() {
         let stdout_reader = BufReader::new(stdout);
         thread::spawn(move || {
-            for line in stdout_reader.lines() {
-                if let Ok(line) = line {
-                    println!("[CLIENT2 STDOUT] {}", line);
-                }
+            for line in stdout_reader.lines().map_while(Result::ok) {
+                println!("[CLIENT2 STDOUT] {}", line);
             }
         });
     }
@@ -115,10 +105,8 @@ fn test_put_and_get() {
     if let Some(stderr) = client2.stderr.take() {
         let stderr_reader = BufReader::new(stderr);
         thread::spawn(move || {
-            for line in stderr_reader.lines() {
-                if let Ok(line) = line {
-                    eprintln!("[CLIENT2 STDERR] {}", line);
-                }
+            for line in stderr_reader.lines().map_while(Result::ok) {
+                eprintln!("[CLIENT2 STDERR] {}", line);
             }
         });
     }
@@ -133,11 +121,11 @@ fn test_put_and_get() {
     println!("Downloaded file content: {}", downloaded_content.trim());
     assert_eq!(original_content, downloaded_content, "Content mismatch after GET");
 
-    let absolute_downloaded_file_path = format!("{}\\{}", cwd.display(), downloaded_file);
+    let absolute_downloaded_file_path = cwd.join(downloaded_file);
     assert!(
-        Path::new(&absolute_downloaded_file_path).exists(),
+        absolute_downloaded_file_path.exists(),
         "Downloaded file does not exist locally: {}",
-        absolute_downloaded_file_path
+        absolute_downloaded_file_path.display()
     );
 
     remove_file(test_file_path).ok();
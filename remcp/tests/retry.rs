@@ -0,0 +1,105 @@
+use std::process::{Command, Stdio};
+use std::net::TcpListener;
+use std::thread;
+use std::time::{Duration, Instant};
+use std::fs::{create_dir_all, read, remove_dir_all, write};
+
+mod common;
+use common::{run_client, spawn_server, start_server};
+
+#[test]
+fn test_retry_until_server_starts() {
+    let work_dir = "retry_test";
+    create_dir_all(format!("{}/served", work_dir)).expect("Failed to create work directory");
+    write(format!("{}/served/late.txt", work_dir), b"worth the wait").expect("Failed to write served file");
+    let local = format!("{}/late.txt", work_dir);
+
+    // Nothing listens yet, so the first attempts are refused
+    let client = Command::new("../target/debug/remcp")
+        .args(["--retries", "8", "--retry-delay", "0.5", "--retry-max-delay", "1", "127.0.0.1:7980:late.txt", &local])
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .expect("Failed to start client");
+    thread::sleep(Duration::from_millis(1500));
+
+    let served = format!("{}/served", work_dir);
+    let mut server = spawn_server(&["--root", &served, "--listen", "127.0.0.1:7980"], "SERVER");
+
+    let output = client.wait_with_output().expect("Failed to wait for client");
+    println!("[CLIENT STDOUT] {}", String::from_utf8_lossy(&output.stdout));
+    eprintln!("[CLIENT STDERR] {}", String::from_utf8_lossy(&output.stderr));
+    let content = read(&local).unwrap_or_default();
+
+    server.kill().ok();
+    server.wait().ok();
+    remove_dir_all(work_dir).ok();
+
+    assert_eq!(output.status.code(), Some(0), "Copy should succeed once the server is up");
+    assert!(String::from_utf8_lossy(&output.stdout).contains("Attempt 2/9"), "Refused connections should be retried");
+    assert_eq!(content, b"worth the wait");
+}
+
+#[test]
+fn test_retry_options() {
+    // With no retries, a refused connection fails after a single attempt
+    let start = Instant::now();
+    let refused = run_client(&["--retries", "0", "127.0.0.1:7981:file.txt", "retry_options_unused.txt"]);
+    assert_eq!(refused.status.code(), Some(3));
+    assert!(String::from_utf8_lossy(&refused.stdout).contains("Attempt 1/1"));
+    assert!(start.elapsed() < Duration::from_secs(1), "No retry should mean no backoff");
+
+    for bad in [["--retries", "-1"], ["--retry-delay", "soon"], ["--retry-max-delay", "-2"]] {
+        let output = run_client(&[bad[0], bad[1], "127.0.0.1:file.txt", "retry_options_unused.txt"]);
+        assert_eq!(output.status.code(), Some(2), "{:?} should be rejected", bad);
+    }
+    let missing = run_client(&["127.0.0.1:file.txt", "retry_options_unused.txt", "--retries"]);
+    assert_eq!(missing.status.code(), Some(2));
+}
+
+#[test]
+fn test_busy_hint_sets_the_wait() {
+    let mut server = start_server(&["--max-clients", "0", "--listen", "127.0.0.1:7983"]);
+
+    // The server asks for 5 seconds, well past the 0.1 second backoff
+    let start = Instant::now();
+    let honoured = run_client(&["--retries", "1", "--retry-delay", "0.1", "127.0.0.1:7983:file.txt", "retry_hint_unused.txt"]);
+    let waited = start.elapsed();
+    // ...unless that is longer than the client is willing to wait
+    let capped = run_client(&["--retries", "1", "--retry-max-delay", "0.5", "127.0.0.1:7983:file.txt", "retry_hint_unused.txt"]);
+
+    server.kill().ok();
+    server.wait().ok();
+
+    assert_eq!(honoured.status.code(), Some(4));
+    assert!(
+        String::from_utf8_lossy(&honoured.stderr).contains("queue position 1). Retrying 1/1 in 5.0 seconds"),
+        "The busy hint should set the wait"
+    );
+    assert!(waited >= Duration::from_secs(5));
+    assert_eq!(capped.status.code(), Some(4));
+    assert!(String::from_utf8_lossy(&capped.stderr).contains("Retrying 1/1 in 0.5 seconds"));
+}
+
+#[test]
+fn test_stalled_server_times_out() {
+    // Accepts connections but never answers them
+    let listener = TcpListener::bind("127.0.0.1:7987").expect("Failed to bind stalled server");
+    thread::spawn(move || {
+        let mut held = Vec::new();
+        for stream in listener.incoming().map_while(Result::ok) {
+            held.push(stream);
+        }
+    });
+
+    let start = Instant::now();
+    let output = run_client(&[
+        "--chunk-timeout", "1", "--retries", "1", "--retry-delay", "0.1",
+        "127.0.0.1:7987:file.txt", "retry_stalled_unused.txt",
+    ]);
+    let elapsed = start.elapsed();
+
+    assert_eq!(output.status.code(), Some(3));
+    assert!(String::from_utf8_lossy(&output.stderr).contains("Retrying 1/1"), "A timeout should be retried");
+    assert!(elapsed < Duration::from_secs(10), "Client waited {:?}", elapsed);
+}
@@ -0,0 +1,44 @@
+use std::fs::{create_dir_all, remove_dir_all, write};
+
+mod common;
+use common::{run_client, start_server};
+
+#[test]
+fn test_stat_and_skip_unchanged() {
+    let work_dir = "stat_test";
+    create_dir_all(format!("{}/sub", work_dir)).expect("Failed to create work directory");
+    let remote_file = format!("{}/stat me.txt", work_dir);
+    write(&remote_file, b"twelve bytes").expect("Failed to write remote file");
+
+    let mut server = start_server(&[]);
+
+    let remote = format!("127.0.0.1:{}", remote_file);
+    let human = run_client(&["--stat", &remote]);
+    let json = run_client(&["--stat", "--json", &remote]);
+    let dir_json = run_client(&["--stat", "--json", &format!("127.0.0.1:{}/sub", work_dir)]);
+    let missing = run_client(&["--stat", &format!("127.0.0.1:{}/missing", work_dir)]);
+
+    let local_file = format!("{}/downloaded.txt", work_dir);
+    let first_get = run_client(&[&remote, &local_file]);
+    let second_get = run_client(&[&remote, &local_file]);
+
+    server.kill().ok();
+    server.wait().ok();
+    remove_dir_all(work_dir).ok();
+
+    let human = String::from_utf8_lossy(&human.stdout);
+    assert!(human.contains("Type: file"), "Missing type in: {}", human);
+    assert!(human.contains("Size: 12"), "Missing size in: {}", human);
+
+    let json = String::from_utf8_lossy(&json.stdout);
+    assert!(json.trim().starts_with('{') && json.trim().ends_with('}'), "Not a JSON object: {}", json);
+    assert!(json.contains("\"path\":\"stat_test/stat me.txt\""), "Missing path in: {}", json);
+    assert!(json.contains("\"type\":\"file\"") && json.contains("\"size\":12"), "Wrong fields in: {}", json);
+    assert!(String::from_utf8_lossy(&dir_json.stdout).contains("\"type\":\"dir\""));
+
+    assert!(!missing.status.success(), "STAT of a missing file succeeded");
+
+    assert!(first_get.status.success(), "First GET failed");
+    let second = String::from_utf8_lossy(&second_get.stdout);
+    assert!(second.contains("up to date"), "Unchanged file was downloaded again: {}", second);
+}
@@ -0,0 +1,91 @@
+use std::fs::{create_dir_all, read, remove_dir_all};
+use std::io::{BufRead, BufReader, Write};
+use std::net::TcpListener;
+use std::path::Path;
+use std::thread;
+
+mod common;
+use common::run_client;
+
+/// A server that lists `entries` as the files of any directory, whatever
+/// they are, and serves `hello` as the content of every file.
+fn spawn_lying_server(port: u16, entries: Vec<String>) {
+    let listener = TcpListener::bind(("127.0.0.1", port)).expect("Failed to bind lying server");
+    thread::spawn(move || {
+        for stream in listener.incoming().map_while(Result::ok) {
+            let entries = entries.clone();
+            thread::spawn(move || {
+                let reader = BufReader::new(stream.try_clone().unwrap());
+                let mut writer = stream;
+                for line in reader.lines().map_while(Result::ok) {
+                    let reply = match line.split(' ').collect::<Vec<_>>().as_slice() {
+                        ["HELLO", ..] => "HELLO 1 stat,list\n".to_string(),
+                        ["STAT", path] if path.ends_with("tree") => "STAT dir 0 0 755\n".to_string(),
+                        ["STAT", _] => "STAT file 5 0 644\n".to_string(),
+                        ["LIST", ..] => {
+                            let listed: String = entries.iter().map(|entry| format!("ENTRY {} file 5 0 644\n", entry)).collect();
+                            format!("{}OK {}\n", listed, entries.len())
+                        }
+                        ["GET", ..] => "OK 5\nNEXT 5\nhello".to_string(),
+                        _ => "ERR Invalid command\n".to_string(),
+                    };
+                    if writer.write_all(reply.as_bytes()).is_err() {
+                        break;
+                    }
+                }
+            });
+        }
+    });
+}
+
+#[test]
+fn test_tree_entries_stay_in_the_destination() {
+    let work_dir = "untrusted_tree_test";
+    let destination = format!("{}/dest", work_dir);
+    create_dir_all(work_dir).expect("Failed to create work directory");
+    let cwd = std::env::current_dir().expect("Failed to get current directory");
+    let absolute = cwd.join(work_dir).join("absolute.txt");
+
+    spawn_lying_server(
+        8005,
+        vec!["good.txt".to_string(), "../escape.txt".to_string(), absolute.display().to_string()],
+    );
+    let output = run_client(&["-r", "127.0.0.1:8005:tree", &destination]);
+
+    let good = read(format!("{}/good.txt", destination)).unwrap_or_default();
+    let escaped = Path::new(&format!("{}/escape.txt", work_dir)).exists();
+    let absolute_written = absolute.exists();
+    remove_dir_all(work_dir).ok();
+
+    assert_eq!(good, b"hello", "The safe entry should still be copied");
+    assert!(!escaped, "An entry with '..' was written outside the destination");
+    assert!(!absolute_written, "An absolute entry was written outside the destination");
+    assert_eq!(output.status.code(), Some(7), "Refused entries should make the copy partial");
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(stdout.contains("failed   ../escape.txt: Path leaves the destination directory"), "{}", stdout);
+}
+
+#[test]
+fn test_wildcard_matches_stay_in_the_destination() {
+    let work_dir = "untrusted_glob_test";
+    let destination = format!("{}/dest", work_dir);
+    create_dir_all(&destination).expect("Failed to create destination directory");
+
+    spawn_lying_server(8006, vec!["good.txt".to_string(), "..".to_string()]);
+    let output = run_client(&["127.0.0.1:8006:tree/*", &destination]);
+
+    let good = read(format!("{}/good.txt", destination)).unwrap_or_default();
+    let mut written: Vec<_> = std::fs::read_dir(work_dir)
+        .expect("Failed to read work directory")
+        .map_while(Result::ok)
+        .map(|entry| entry.file_name())
+        .collect();
+    written.sort();
+    remove_dir_all(work_dir).ok();
+
+    assert_eq!(good, b"hello", "The safe match should still be copied");
+    assert_eq!(written, ["dest"], "Nothing should be written next to the destination");
+    assert_eq!(output.status.code(), Some(7), "Refused matches should make the copy partial");
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(stdout.contains("failed   tree/..: Path leaves the destination directory"), "{}", stdout);
+}
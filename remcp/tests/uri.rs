@@ -0,0 +1,64 @@
+use std::process::{Command, Stdio};
+use std::io::{BufRead, BufReader};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+use std::fs::{create_dir_all, read, remove_dir_all, write};
+
+mod common;
+use common::run_client;
+
+#[test]
+fn test_uri_and_local_escapes() {
+    let work_dir = "uri_test";
+    create_dir_all(format!("{}/served", work_dir)).expect("Failed to create work directory");
+    let colon_file = format!("./{}/notes:v2.txt", work_dir);
+    write(&colon_file, b"a local name with a colon").expect("Failed to write local file");
+
+    let mut server = Command::new("../target/debug/remcp-serv")
+        .args(["--debug", "--root", &format!("{}/served", work_dir)])
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .expect("Failed to start server");
+
+    let server_log = Arc::new(Mutex::new(String::new()));
+    if let Some(stdout) = server.stdout.take() {
+        let server_log = Arc::clone(&server_log);
+        thread::spawn(move || {
+            for line in BufReader::new(stdout).lines().map_while(Result::ok) {
+                println!("[SERVER STDOUT] {}", line);
+                server_log.lock().unwrap().push_str(&line);
+            }
+        });
+    }
+
+    thread::sleep(Duration::from_secs(2));
+
+    let put = run_client(&[&colon_file, "remcp://tester@127.0.0.1:7878/uri%20dir/up.txt"]);
+    let downloaded = format!("{}/down:loaded.txt", work_dir);
+    let get = run_client(&["127.0.0.1:uri dir/up.txt", &format!("./{}", downloaded)]);
+    let ls_root = run_client(&["ls", "127.0.0.1:"]);
+    let ls_uri = run_client(&["ls", "remcp://127.0.0.1/uri%20dir"]);
+    let bad_port = run_client(&["remcp://127.0.0.1:port/x", "local.txt"]);
+
+    server.kill().ok();
+    server.wait().ok();
+
+    let uploaded = read(format!("{}/served/uri dir/up.txt", work_dir)).unwrap_or_default();
+    let copy = read(&downloaded).unwrap_or_default();
+    remove_dir_all(work_dir).ok();
+
+    let original = b"a local name with a colon".to_vec();
+    assert!(String::from_utf8_lossy(&put.stdout).contains("PUT operation succeeded"), "PUT to a URI failed");
+    assert_eq!(uploaded, original, "Upload content mismatch");
+    assert!(server_log.lock().unwrap().contains("identifies as user 'tester'"), "User was not sent in HELLO");
+    assert!(get.status.success() && copy == original, "GET into a local name with a colon failed");
+
+    assert!(ls_root.status.success(), "Listing an empty remote path failed");
+    assert!(String::from_utf8_lossy(&ls_root.stdout).contains("uri dir/"), "Root listing is missing the upload");
+    assert!(String::from_utf8_lossy(&ls_uri.stdout).contains("up.txt"), "Listing a URI failed");
+
+    assert!(!bad_port.status.success(), "A malformed URI was accepted");
+    assert!(String::from_utf8_lossy(&bad_port.stderr).contains("Invalid remote path"));
+}
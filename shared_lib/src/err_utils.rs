@@ -1,15 +1,56 @@
+use std::convert::Infallible;
 use std::fmt;
+use std::io;
+use std::str::FromStr;
 
-#[derive(Debug)]
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub enum GetError {
     InvalidCommand,
     MissingArguments,
     FileError(String),
     UnknownCommand,
-    ServerBusy,
+    /// Without a hint when the server did not estimate the wait.
+    ServerBusy(Option<BusyHint>),
+    /// The server has room, but not for another connection from this client;
+    /// says which of the server's per-client limits was hit.
+    LimitReached(String),
+    AccessDenied(String),
+    VersionMismatch { client: u32, server: u32 },
+    IntegrityError(String),
+    NotFound(String),
+    NotEmpty(String),
+    PermissionDenied(String),
     Other(String),
 }
 
+/// When a busy server expects to have room for the client again.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BusyHint {
+    /// Seconds to wait before trying again.
+    pub retry_after: u64,
+    /// 1 for the next client to get in, 2 for the one after it, and so on.
+    pub position: usize,
+}
+
+impl GetError {
+    /// Returns the `GetError` carried by an `io::Error`, if any. Errors
+    /// decoded from the wire travel through `io::Result` this way.
+    pub fn from_io(err: &io::Error) -> Option<&GetError> {
+        err.get_ref().and_then(|inner| inner.downcast_ref::<GetError>())
+    }
+
+    /// Describes a failed filesystem operation on `path` for the client,
+    /// keeping the kinds it can act on distinct from other I/O errors.
+    pub fn from_fs(err: &io::Error, path: &str) -> GetError {
+        match err.kind() {
+            io::ErrorKind::NotFound => GetError::NotFound(path.to_string()),
+            io::ErrorKind::DirectoryNotEmpty => GetError::NotEmpty(path.to_string()),
+            io::ErrorKind::PermissionDenied => GetError::PermissionDenied(path.to_string()),
+            _ => GetError::FileError(err.to_string()),
+        }
+    }
+}
+
 impl fmt::Display for GetError {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
@@ -17,7 +58,21 @@ impl fmt::Display for GetError {
             GetError::MissingArguments => write!(f, "Missing arguments"),
             GetError::FileError(err) => write!(f, "File error: {}", err),
             GetError::UnknownCommand => write!(f, "Unknown command"),
-            GetError::ServerBusy => write!(f, "Server is busy"),
+            GetError::ServerBusy(None) => write!(f, "Server is busy"),
+            GetError::ServerBusy(Some(hint)) => write!(
+                f,
+                "Server is busy (retry after {}s, queue position {})",
+                hint.retry_after, hint.position
+            ),
+            GetError::LimitReached(limit) => write!(f, "Client limit reached: {}", limit),
+            GetError::AccessDenied(path) => write!(f, "Access denied: {}", path),
+            GetError::VersionMismatch { client, server } => {
+                write!(f, "Protocol version mismatch (client {}, server {})", client, server)
+            }
+            GetError::IntegrityError(err) => write!(f, "Integrity check failed: {}", err),
+            GetError::NotFound(path) => write!(f, "Not found: {}", path),
+            GetError::NotEmpty(path) => write!(f, "Directory not empty: {}", path),
+            GetError::PermissionDenied(path) => write!(f, "Permission denied: {}", path),
             GetError::Other(err) => write!(f, "Other error: {}", err),
         }
     }
@@ -25,69 +80,66 @@ impl fmt::Display for GetError {
 
 impl std::error::Error for GetError {}
 
-pub fn parse_error(response: &str) -> GetError {
-    match response {
-        "ERR Invalid command" => GetError::InvalidCommand,
-        "ERR Missing arguments" => GetError::MissingArguments,
-        "ERR Unknown command" => GetError::UnknownCommand,
-        "ERR Server busy" => GetError::ServerBusy,
-        _ if response.starts_with("ERR ") => GetError::FileError(response[4..].to_string()),
-        _ => GetError::Other(response.to_string()),
+/// Parses the text produced by `Display`, which is what follows `ERR ` on the wire.
+impl FromStr for GetError {
+    type Err = Infallible;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(match s {
+            "Invalid command" => GetError::InvalidCommand,
+            "Missing arguments" => GetError::MissingArguments,
+            "Unknown command" => GetError::UnknownCommand,
+            "Server is busy" => GetError::ServerBusy(None),
+            _ => {
+                if let Some(hint) = parse_busy_hint(s) {
+                    GetError::ServerBusy(Some(hint))
+                } else if let Some(err) = s.strip_prefix("File error: ") {
+                    GetError::FileError(err.to_string())
+                } else if let Some(limit) = s.strip_prefix("Client limit reached: ") {
+                    GetError::LimitReached(limit.to_string())
+                } else if let Some(path) = s.strip_prefix("Access denied: ") {
+                    GetError::AccessDenied(path.to_string())
+                } else if let Some(err) = s.strip_prefix("Integrity check failed: ") {
+                    GetError::IntegrityError(err.to_string())
+                } else if let Some(path) = s.strip_prefix("Not found: ") {
+                    GetError::NotFound(path.to_string())
+                } else if let Some(path) = s.strip_prefix("Directory not empty: ") {
+                    GetError::NotEmpty(path.to_string())
+                } else if let Some(path) = s.strip_prefix("Permission denied: ") {
+                    GetError::PermissionDenied(path.to_string())
+                } else if let Some(versions) = parse_version_mismatch(s) {
+                    versions
+                } else if let Some(err) = s.strip_prefix("Other error: ") {
+                    GetError::Other(err.to_string())
+                } else {
+                    GetError::Other(s.to_string())
+                }
+            }
+        })
     }
 }
 
-pub enum ServerResponse {
-    Ok,
-    Error(GetError),
-    Next(usize),
+fn parse_busy_hint(s: &str) -> Option<BusyHint> {
+    let hint = s.strip_prefix("Server is busy (retry after ")?.strip_suffix(')')?;
+    let (retry_after, position) = hint.split_once("s, queue position ")?;
+    Some(BusyHint {
+        retry_after: retry_after.parse().ok()?,
+        position: position.parse().ok()?,
+    })
 }
 
-impl ServerResponse {
-    pub fn from_response(response: &str) -> Self {
-        if response.starts_with("ERR") {
-            ServerResponse::Error(parse_error(response))
-        } else if response.starts_with("OK") {
-            ServerResponse::Ok
-        } else if response.starts_with("NEXT ") {
-            let parts: Vec<&str> = response.split_whitespace().collect();
-            if parts.len() == 2 {
-                if let Ok(sz) = parts[1].parse::<usize>() {
-                    return ServerResponse::Next(sz);
-                }
-            }
-            ServerResponse::Error(GetError::Other("Invalid NEXT command format".to_string()))
-        } else {
-            ServerResponse::Error(GetError::Other("Invalid response".to_string()))
-        }
-    }
+fn parse_version_mismatch(s: &str) -> Option<GetError> {
+    let versions = s.strip_prefix("Protocol version mismatch (client ")?.strip_suffix(')')?;
+    let (client, server) = versions.split_once(", server ")?;
+    Some(GetError::VersionMismatch {
+        client: client.parse().ok()?,
+        server: server.parse().ok()?,
+    })
 }
 
-pub fn parse_server_response(line: &str) -> ServerResponse {
-    if line.starts_with("ERR ") {
-        let err_str = &line[4..];
-        if err_str == "Invalid command" {
-            ServerResponse::Error(GetError::InvalidCommand)
-        } else if err_str == "Missing arguments" {
-            ServerResponse::Error(GetError::MissingArguments)
-        } else if err_str == "Unknown command" {
-            ServerResponse::Error(GetError::UnknownCommand)
-        } else if err_str == "Server is busy" {
-            ServerResponse::Error(GetError::ServerBusy)
-        } else {
-            ServerResponse::Error(GetError::Other(err_str.to_string()))
-        }
-    } else if line.starts_with("OK") {
-        ServerResponse::Ok
-    } else if line.starts_with("NEXT ") {
-        let parts: Vec<&str> = line.split_whitespace().collect();
-        if parts.len() == 2 {
-            if let Ok(sz) = parts[1].parse::<usize>() {
-                return ServerResponse::Next(sz);
-            }
-        }
-        ServerResponse::Error(GetError::Other("Invalid NEXT command format".to_string()))
-    } else {
-        ServerResponse::Error(GetError::Other("Invalid response".to_string()))
+impl From<GetError> for io::Error {
+    fn from(err: GetError) -> Self {
+        io::Error::other(err)
     }
 }
 
@@ -95,66 +147,74 @@ pub fn parse_server_response(line: &str) -> ServerResponse {
 mod tests {
     use super::*;
 
+    fn parse(s: &str) -> GetError {
+        s.parse().unwrap()
+    }
+
     #[test]
     fn test_parse_error() {
-        assert_eq!(parse_error("ERR Invalid command").to_string(), "Invalid command");
-        assert_eq!(parse_error("ERR Missing arguments").to_string(), "Missing arguments");
-        assert_eq!(parse_error("ERR Server busy").to_string(), "Server is busy");
+        assert_eq!(parse("Invalid command"), GetError::InvalidCommand);
+        assert_eq!(parse("Missing arguments"), GetError::MissingArguments);
+        assert_eq!(parse("Server is busy").to_string(), "Server is busy");
         assert_eq!(
-            parse_error("ERR File not found").to_string(),
+            parse("Server is busy (retry after 12s, queue position 3)"),
+            GetError::ServerBusy(Some(BusyHint { retry_after: 12, position: 3 }))
+        );
+        assert_eq!(
+            parse("Server is busy (retry after soon)"),
+            GetError::Other("Server is busy (retry after soon)".to_string())
+        );
+        assert_eq!(
+            parse("File error: File not found").to_string(),
             "File error: File not found"
         );
         assert_eq!(
-            parse_error("Unexpected error").to_string(),
+            parse("Unexpected error").to_string(),
             "Other error: Unexpected error"
         );
     }
 
     #[test]
-    fn test_server_response() {
-        match ServerResponse::from_response("ERR Invalid command") {
-            ServerResponse::Error(err) => assert_eq!(err.to_string(), "Invalid command"),
-            _ => panic!("Expected an error response"),
-        }
-
-        match ServerResponse::from_response("OK") {
-            ServerResponse::Ok => {}
-            _ => panic!("Expected an OK response"),
+    fn test_display_round_trip() {
+        let errors = vec![
+            GetError::InvalidCommand,
+            GetError::MissingArguments,
+            GetError::FileError("No such file or directory (os error 2)".to_string()),
+            GetError::UnknownCommand,
+            GetError::ServerBusy(None),
+            GetError::ServerBusy(Some(BusyHint { retry_after: 5, position: 1 })),
+            GetError::LimitReached("2 connections from 10.0.0.5".to_string()),
+            GetError::AccessDenied("../secret".to_string()),
+            GetError::VersionMismatch { client: 0, server: 1 },
+            GetError::IntegrityError("chunk at offset 512".to_string()),
+            GetError::NotFound("missing.txt".to_string()),
+            GetError::NotEmpty("docs".to_string()),
+            GetError::PermissionDenied("secret/".to_string()),
+            GetError::Other("Invalid response".to_string()),
+        ];
+        for err in errors {
+            assert_eq!(parse(&err.to_string()), err);
         }
     }
 
     #[test]
-    fn test_server_response_next() {
-        match ServerResponse::from_response("NEXT 64") {
-            ServerResponse::Next(size) => assert_eq!(size, 64),
-            _ => panic!("Expected a NEXT 64 response"),
-        }
+    fn test_from_io() {
+        let io_err: io::Error = GetError::ServerBusy(None).into();
+        assert_eq!(GetError::from_io(&io_err), Some(&GetError::ServerBusy(None)));
 
-        match ServerResponse::from_response("NEXT abc") {
-            ServerResponse::Error(err) => {
-                assert_eq!(err.to_string(), "Other error: Invalid NEXT command format")
-            }
-            _ => panic!("Expected an error for invalid NEXT format"),
-        }
-
-        match ServerResponse::from_response("UNKNOWN") {
-            ServerResponse::Error(err) => {
-                assert!(err.to_string().contains("Invalid response"))
-            },
-            _ => panic!("Expected invalid response error"),
-        }
+        let plain = io::Error::new(io::ErrorKind::ConnectionReset, "reset");
+        assert_eq!(GetError::from_io(&plain), None);
     }
 
     #[test]
-    fn test_server_response_ok_and_err() {
-        match ServerResponse::from_response("ERR Invalid command") {
-            ServerResponse::Error(err) => assert_eq!(err.to_string(), "Invalid command"),
-            _ => panic!("Expected an error response"),
-        }
-
-        match ServerResponse::from_response("OK") {
-            ServerResponse::Ok => {}
-            _ => panic!("Expected an OK response"),
-        }
+    fn test_from_fs() {
+        let not_found = io::Error::from(io::ErrorKind::NotFound);
+        assert_eq!(GetError::from_fs(&not_found, "a.txt"), GetError::NotFound("a.txt".to_string()));
+        let not_empty = io::Error::from(io::ErrorKind::DirectoryNotEmpty);
+        assert_eq!(GetError::from_fs(&not_empty, "dir"), GetError::NotEmpty("dir".to_string()));
+        let denied = io::Error::from(io::ErrorKind::PermissionDenied);
+        assert_eq!(GetError::from_fs(&denied, "x"), GetError::PermissionDenied("x".to_string()));
+        let other = io::Error::other("disk on fire");
+        assert_eq!(GetError::from_fs(&other, "x"), GetError::FileError("disk on fire".to_string()));
     }
-}
\ No newline at end of file
+}
@@ -0,0 +1,133 @@
+/// Shell-style wildcard matching on raw path bytes.
+///
+/// `*` matches any run of bytes and `?` any single byte, neither crossing
+/// `/`. `[abc]`, `[a-z]` and `[!abc]` match one byte from (or outside) a set,
+/// and `\` makes the next byte literal.
+pub fn glob_match(pattern: &[u8], name: &[u8]) -> bool {
+    let (mut p, mut n) = (0, 0);
+    // Where to resume after the last `*` if the current attempt fails
+    let mut backtrack: Option<(usize, usize)> = None;
+
+    while n < name.len() {
+        let step = match pattern.get(p) {
+            Some(b'*') => {
+                backtrack = Some((p, n));
+                p += 1;
+                continue;
+            }
+            Some(b'?') if name[n] != b'/' => Some(p + 1),
+            Some(b'[') => match match_class(&pattern[p..], name[n]) {
+                Some((true, len)) => Some(p + len),
+                Some((false, _)) => None,
+                // An unterminated class is a literal `[`
+                None if name[n] == b'[' => Some(p + 1),
+                None => None,
+            },
+            Some(b'\\') if p + 1 < pattern.len() && pattern[p + 1] == name[n] => Some(p + 2),
+            Some(&c) if c != b'\\' && c == name[n] => Some(p + 1),
+            _ => None,
+        };
+
+        match step {
+            Some(next) => {
+                p = next;
+                n += 1;
+            }
+            None => match backtrack {
+                // Let the last `*` swallow one more byte, but never a `/`
+                Some((star, start)) if name[start] != b'/' => {
+                    backtrack = Some((star, start + 1));
+                    p = star + 1;
+                    n = start + 1;
+                }
+                _ => return false,
+            },
+        }
+    }
+
+    pattern[p..].iter().all(|&c| c == b'*')
+}
+
+/// Matches `byte` against the class at the start of `class`. Returns whether
+/// it matched and the length of the class, or `None` if it is unterminated.
+fn match_class(class: &[u8], byte: u8) -> Option<(bool, usize)> {
+    let mut i = 1;
+    let negated = matches!(class.get(i), Some(b'!') | Some(b'^'));
+    if negated {
+        i += 1;
+    }
+
+    let mut matched = false;
+    let mut first = true;
+    while i < class.len() {
+        let c = class[i];
+        if c == b']' && !first {
+            return Some((matched != negated && byte != b'/', i + 1));
+        }
+        first = false;
+
+        if i + 2 < class.len() && class[i + 1] == b'-' && class[i + 2] != b']' {
+            matched |= (c..=class[i + 2]).contains(&byte);
+            i += 3;
+        } else {
+            matched |= c == byte;
+            i += 1;
+        }
+    }
+    None
+}
+
+/// Whether `pattern` contains any wildcard, i.e. is not a plain name.
+pub fn is_glob(pattern: &[u8]) -> bool {
+    pattern.iter().any(|c| matches!(c, b'*' | b'?' | b'['))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn matches(pattern: &str, name: &str) -> bool {
+        glob_match(pattern.as_bytes(), name.as_bytes())
+    }
+
+    #[test]
+    fn test_wildcards() {
+        assert!(matches("*.txt", "notes.txt"));
+        assert!(matches("*.txt", ".txt"));
+        assert!(!matches("*.txt", "notes.txt.bak"));
+        assert!(matches("a*b*c", "aXXbYYc"));
+        assert!(!matches("a*b*c", "aXXbYY"));
+        assert!(matches("file?.bin", "file1.bin"));
+        assert!(!matches("file?.bin", "file10.bin"));
+        assert!(matches("*", ""));
+        assert!(matches("exact", "exact"));
+        assert!(!matches("exact", "exactly"));
+    }
+
+    #[test]
+    fn test_classes() {
+        assert!(matches("report[0-9].pdf", "report7.pdf"));
+        assert!(!matches("report[0-9].pdf", "reportx.pdf"));
+        assert!(matches("[!a]*", "bcd"));
+        assert!(!matches("[!a]*", "abc"));
+        assert!(matches("[]x]", "]"));
+        assert!(matches("a[", "a["));
+        assert!(matches("\\*literal", "*literal"));
+        assert!(!matches("\\*literal", "xliteral"));
+    }
+
+    #[test]
+    fn test_slashes() {
+        assert!(matches("dir/*.txt", "dir/a.txt"));
+        assert!(!matches("*.txt", "dir/a.txt"));
+        assert!(!matches("dir?a.txt", "dir/a.txt"));
+        assert!(matches("*/*", "a/b"));
+    }
+
+    #[test]
+    fn test_is_glob() {
+        assert!(is_glob(b"*.txt"));
+        assert!(is_glob(b"file[12]"));
+        assert!(!is_glob(b"plain name.txt"));
+    }
+}
@@ -1,26 +1,107 @@
-use std::path::{Path, PathBuf};
+use std::borrow::Cow;
+use std::ffi::{OsStr, OsString};
+use std::path::{Component, Path, PathBuf};
+pub mod checksum;
 pub mod debug_utils;
 mod err_utils;
+pub mod fmt_utils;
+pub mod glob;
+pub mod mux;
+mod protocol;
+pub mod remote;
 
-pub use err_utils::{GetError, parse_server_response, ServerResponse};
+pub use err_utils::{BusyHint, GetError};
+pub use protocol::{
+    CAPABILITIES, CAP_CHECKSUMS, CAP_FILE_OPS, CAP_LIST, CAP_MUX, CAP_SESSION, CAP_STAT, PROTOCOL_VERSION, FileKind, FileStat, Request, Response,
+    decode_path, encode_path, has_capability, negotiate_capabilities,
+};
 
-pub fn normalize_path(path: &str) -> PathBuf {
+/// Raw bytes of an OS string. Exact on Unix; elsewhere paths are carried as UTF-8.
+pub fn os_str_bytes(s: &OsStr) -> Cow<'_, [u8]> {
+    #[cfg(unix)]
+    {
+        use std::os::unix::ffi::OsStrExt;
+        Cow::Borrowed(s.as_bytes())
+    }
+    #[cfg(not(unix))]
+    {
+        match s.to_string_lossy() {
+            Cow::Borrowed(s) => Cow::Borrowed(s.as_bytes()),
+            Cow::Owned(s) => Cow::Owned(s.into_bytes()),
+        }
+    }
+}
+
+/// Inverse of `os_str_bytes`.
+pub fn os_string_from_bytes(bytes: Vec<u8>) -> OsString {
+    #[cfg(unix)]
+    {
+        use std::os::unix::ffi::OsStringExt;
+        OsString::from_vec(bytes)
+    }
+    #[cfg(not(unix))]
+    {
+        OsString::from(String::from_utf8_lossy(&bytes).into_owned())
+    }
+}
+
+pub fn normalize_path<P: AsRef<OsStr>>(path: P) -> PathBuf {
     if cfg!(windows) {
+        let path = path.as_ref().to_string_lossy();
         if path.starts_with('/') {
             Path::new(&format!("C:{}", path.replace('/', "\\"))).to_path_buf()
         } else {
             Path::new(&path.replace('/', "\\")).to_path_buf()
         }
     } else {
-        Path::new(path).to_path_buf()
+        Path::new(path.as_ref()).to_path_buf()
+    }
+}
+
+/// Resolves a client-supplied path inside `root`, which must already be
+/// canonical. Absolute paths and `..` components are rejected outright, and
+/// the deepest existing ancestor of the result is canonicalized so a symlink
+/// cannot lead outside the root.
+pub fn resolve_in_root(root: &Path, requested: &OsStr) -> Result<PathBuf, GetError> {
+    let denied = || GetError::AccessDenied(requested.to_string_lossy().into_owned());
+
+    let requested = if cfg!(windows) {
+        PathBuf::from(requested.to_string_lossy().replace('/', "\\"))
+    } else {
+        PathBuf::from(requested)
+    };
+
+    let mut resolved = root.to_path_buf();
+    for component in requested.components() {
+        match component {
+            Component::Normal(part) => resolved.push(part),
+            Component::CurDir => {}
+            Component::ParentDir | Component::RootDir | Component::Prefix(_) => return Err(denied()),
+        }
     }
+
+    let mut existing = resolved.as_path();
+    while existing.symlink_metadata().is_err() {
+        existing = match existing.parent() {
+            Some(parent) => parent,
+            None => return Err(denied()),
+        };
+    }
+    let canonical = existing.canonicalize().map_err(|_| denied())?;
+    if !canonical.starts_with(root) {
+        return Err(denied());
+    }
+
+    Ok(resolved)
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
 
+    // Only Windows maps `/` onto a drive; the baseline assertion never held elsewhere
     #[test]
+    #[cfg(windows)]
     fn test_normalize_path_windows_absolute() {
         let input_path = "/Users/gabri/OneDrive/Área de Trabalho/odo/remcp_project/target/debug";
         let expected_path = r"C:\Users\gabri\OneDrive\Área de Trabalho\odo\remcp_project\target\debug";
@@ -28,4 +109,72 @@ mod tests {
         let normalized_path = normalize_path(input_path);
         assert_eq!(normalized_path.to_str().unwrap(), expected_path);
     }
-}
\ No newline at end of file
+
+    #[test]
+    #[cfg(not(windows))]
+    fn test_normalize_path_unix_passthrough() {
+        let input_path = "/home/gabri/Área de Trabalho/remcp_project/target/debug";
+
+        let normalized_path = normalize_path(input_path);
+        assert_eq!(normalized_path.to_str().unwrap(), input_path);
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn test_normalize_path_keeps_raw_bytes() {
+        // Latin-1 "Área" and a stray byte, neither of them valid UTF-8
+        let raw = os_string_from_bytes(b"/home/gabri/\xC1rea de Trabalho/dados\xFF.bin".to_vec());
+
+        let received = decode_path(&encode_path(&raw)).unwrap();
+        let normalized_path = normalize_path(&received);
+        assert_eq!(normalized_path.as_os_str(), raw.as_os_str());
+    }
+
+    fn test_root(name: &str) -> PathBuf {
+        let root = std::env::temp_dir().join(format!("remcp_jail_{}_{}", name, std::process::id()));
+        let _ = std::fs::remove_dir_all(&root);
+        std::fs::create_dir_all(root.join("sub")).unwrap();
+        root.canonicalize().unwrap()
+    }
+
+    #[test]
+    fn test_resolve_in_root() {
+        let root = test_root("resolve");
+
+        assert_eq!(resolve_in_root(&root, OsStr::new("a.txt")).unwrap(), root.join("a.txt"));
+        assert_eq!(
+            resolve_in_root(&root, OsStr::new("./sub/new dir/b.txt")).unwrap(),
+            root.join("sub").join("new dir").join("b.txt")
+        );
+
+        for bad in ["../secret", "sub/../../secret", "sub/..", "/etc/passwd"] {
+            assert_eq!(
+                resolve_in_root(&root, OsStr::new(bad)),
+                Err(GetError::AccessDenied(bad.to_string())),
+                "{} should be denied",
+                bad
+            );
+        }
+
+        std::fs::remove_dir_all(&root).ok();
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn test_resolve_in_root_symlinks() {
+        let root = test_root("symlink");
+        let outside = test_root("outside");
+        std::os::unix::fs::symlink(&outside, root.join("escape")).unwrap();
+        std::os::unix::fs::symlink(root.join("sub"), root.join("inside")).unwrap();
+
+        assert!(resolve_in_root(&root, OsStr::new("escape")).is_err());
+        assert!(resolve_in_root(&root, OsStr::new("escape/new/file.txt")).is_err());
+        assert_eq!(
+            resolve_in_root(&root, OsStr::new("inside/file.txt")).unwrap(),
+            root.join("inside").join("file.txt")
+        );
+
+        std::fs::remove_dir_all(&root).ok();
+        std::fs::remove_dir_all(&outside).ok();
+    }
+}
@@ -0,0 +1,343 @@
+use std::collections::HashMap;
+use std::io::{self, BufRead, Read, Write};
+use std::sync::mpsc::{sync_channel, Receiver, RecvTimeoutError, SyncSender};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use crate::err_utils::GetError;
+
+/// Largest payload of a single frame. Longer writes are split, so one big
+/// chunk never holds up the other streams for long.
+pub const MAX_FRAME_SIZE: usize = 64 * 1024;
+
+/// Streams one connection may have open at once.
+pub const MAX_STREAMS: usize = 4;
+
+/// Frames a stream buffers before delivering more blocks, so a peer that
+/// sends faster than the stream is read is held back instead of buffered.
+const STREAM_WINDOW: usize = 4;
+
+/// Writes `FRAME <stream> <len>` followed by the payload. An empty payload
+/// closes the stream in that direction.
+pub fn write_frame<W: Write>(writer: &mut W, stream: u32, payload: &[u8]) -> io::Result<()> {
+    writeln!(writer, "FRAME {} {}", stream, payload.len())?;
+    writer.write_all(payload)
+}
+
+/// Reads the next frame. Returns `None` when the peer closed the connection
+/// between frames.
+pub fn read_frame<R: BufRead>(reader: &mut R) -> io::Result<Option<(u32, Vec<u8>)>> {
+    let mut line = String::new();
+    if reader.read_line(&mut line)? == 0 {
+        return Ok(None);
+    }
+
+    let malformed = || io::Error::new(io::ErrorKind::InvalidData, GetError::InvalidCommand);
+    let parts: Vec<&str> = line.split_whitespace().collect();
+    let (stream, len) = match parts.as_slice() {
+        ["FRAME", stream, len] => (
+            stream.parse().map_err(|_| malformed())?,
+            len.parse::<usize>().map_err(|_| malformed())?,
+        ),
+        _ => return Err(malformed()),
+    };
+    if len > MAX_FRAME_SIZE {
+        return Err(malformed());
+    }
+
+    let mut payload = vec![0u8; len];
+    reader.read_exact(&mut payload)?;
+    Ok(Some((stream, payload)))
+}
+
+/// The sending half of one stream. Writes are buffered and go out as frames
+/// on `flush`; dropping it closes the stream.
+pub struct StreamWriter<W: Write> {
+    stream: u32,
+    link: Arc<Mutex<W>>,
+    buffer: Vec<u8>,
+    peer_closed: Option<Arc<AtomicBool>>,
+}
+
+impl<W: Write> StreamWriter<W> {
+    pub fn new(stream: u32, link: Arc<Mutex<W>>) -> StreamWriter<W> {
+        StreamWriter { stream, link, buffer: Vec::new(), peer_closed: None }
+    }
+
+    /// Makes writes fail with `ConnectionReset` once `closed`, from
+    /// `StreamReader::closed`, is set, so that a reply the peer gave up on
+    /// stops taking up the connection.
+    pub fn abort_when_closed(&mut self, closed: Arc<AtomicBool>) {
+        self.peer_closed = Some(closed);
+    }
+
+    fn check_open(&self) -> io::Result<()> {
+        match &self.peer_closed {
+            Some(closed) if closed.load(Ordering::SeqCst) => {
+                Err(io::Error::new(io::ErrorKind::ConnectionReset, "Stream closed by the peer"))
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+impl<W: Write> Write for StreamWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.check_open()?;
+        self.buffer.extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        if self.buffer.is_empty() {
+            return Ok(());
+        }
+        self.check_open()?;
+        let mut link = self.link.lock().unwrap();
+        for payload in self.buffer.chunks(MAX_FRAME_SIZE) {
+            write_frame(&mut *link, self.stream, payload)?;
+        }
+        self.buffer.clear();
+        link.flush()
+    }
+}
+
+impl<W: Write> Drop for StreamWriter<W> {
+    fn drop(&mut self) {
+        let _ = self.flush();
+        let mut link = self.link.lock().unwrap();
+        let _ = write_frame(&mut *link, self.stream, &[]).and_then(|_| link.flush());
+    }
+}
+
+/// The receiving half of one stream, fed by a `Demux`. Reads hit EOF once
+/// the peer closes the stream or the connection goes away.
+pub struct StreamReader {
+    frames: Receiver<Vec<u8>>,
+    current: Vec<u8>,
+    pos: usize,
+    timeout: Option<Duration>,
+    closed: Arc<AtomicBool>,
+}
+
+impl StreamReader {
+    /// Set once the peer closes the stream or the connection is lost, even
+    /// while frames are still waiting to be read.
+    pub fn closed(&self) -> Arc<AtomicBool> {
+        Arc::clone(&self.closed)
+    }
+
+    /// Like `TcpStream::set_read_timeout`: a read that waits longer than
+    /// `timeout` for the next frame fails with `TimedOut`.
+    pub fn set_read_timeout(&mut self, timeout: Option<Duration>) {
+        self.timeout = timeout;
+    }
+}
+
+impl Read for StreamReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.pos == self.current.len() {
+            let frame = match self.timeout {
+                Some(timeout) => self.frames.recv_timeout(timeout),
+                None => self.frames.recv().map_err(|_| RecvTimeoutError::Disconnected),
+            };
+            match frame {
+                Ok(frame) => {
+                    self.current = frame;
+                    self.pos = 0;
+                }
+                Err(RecvTimeoutError::Timeout) => {
+                    return Err(io::Error::new(io::ErrorKind::TimedOut, "Timed out waiting for the stream"));
+                }
+                Err(RecvTimeoutError::Disconnected) => return Ok(0),
+            }
+        }
+        let len = std::cmp::min(buf.len(), self.current.len() - self.pos);
+        buf[..len].copy_from_slice(&self.current[self.pos..self.pos + len]);
+        self.pos += len;
+        Ok(len)
+    }
+}
+
+/// Routes incoming frames to the reader of the stream they belong to. Shared
+/// between the thread reading the connection and the ones opening streams.
+#[derive(Default)]
+pub struct Demux {
+    streams: Mutex<HashMap<u32, Inbound>>,
+}
+
+/// What a `Demux` keeps of an open stream.
+struct Inbound {
+    frames: SyncSender<Vec<u8>>,
+    closed: Arc<AtomicBool>,
+}
+
+impl Demux {
+    pub fn new() -> Demux {
+        Demux::default()
+    }
+
+    /// Starts accepting frames for `stream` and returns its reader.
+    pub fn open(&self, stream: u32) -> StreamReader {
+        let (sender, frames) = sync_channel(STREAM_WINDOW);
+        let closed = Arc::new(AtomicBool::new(false));
+        self.streams.lock().unwrap().insert(stream, Inbound { frames: sender, closed: Arc::clone(&closed) });
+        StreamReader { frames, current: Vec::new(), pos: 0, timeout: None, closed }
+    }
+
+    pub fn is_open(&self, stream: u32) -> bool {
+        self.streams.lock().unwrap().contains_key(&stream)
+    }
+
+    /// Hands a frame to its stream, waiting while the stream's window is
+    /// full. The wait happens outside the lock, so streams can still be
+    /// opened and closed meanwhile. An empty frame closes the stream.
+    /// Returns `false` if the stream is not open or its reader is gone.
+    pub fn deliver(&self, stream: u32, payload: Vec<u8>) -> bool {
+        if payload.is_empty() {
+            let Some(inbound) = self.streams.lock().unwrap().remove(&stream) else {
+                return false;
+            };
+            inbound.closed.store(true, Ordering::SeqCst);
+            return true;
+        }
+        let Some(sender) = self.streams.lock().unwrap().get(&stream).map(|inbound| inbound.frames.clone()) else {
+            return false;
+        };
+        if sender.send(payload).is_ok() {
+            return true;
+        }
+        self.streams.lock().unwrap().remove(&stream);
+        false
+    }
+
+    /// Ends every stream, as when the connection itself is lost.
+    pub fn close_all(&self) {
+        for (_, inbound) in self.streams.lock().unwrap().drain() {
+            inbound.closed.store(true, Ordering::SeqCst);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    #[test]
+    fn test_frame_round_trip() {
+        let mut wire = Vec::new();
+        write_frame(&mut wire, 1, b"GET a.txt 0\n").unwrap();
+        write_frame(&mut wire, 7, b"").unwrap();
+        write_frame(&mut wire, 2, &[0, b'\n', 255]).unwrap();
+
+        let mut reader = Cursor::new(wire);
+        assert_eq!(read_frame(&mut reader).unwrap(), Some((1, b"GET a.txt 0\n".to_vec())));
+        assert_eq!(read_frame(&mut reader).unwrap(), Some((7, Vec::new())));
+        assert_eq!(read_frame(&mut reader).unwrap(), Some((2, vec![0, b'\n', 255])));
+        assert_eq!(read_frame(&mut reader).unwrap(), None);
+
+        for bad in ["GET a.txt 0\n", "FRAME x 1\n", "FRAME 1 99999999\n"] {
+            assert!(read_frame(&mut Cursor::new(bad)).is_err(), "{:?} should be rejected", bad);
+        }
+    }
+
+    #[test]
+    fn test_streams_interleave() {
+        let link = Arc::new(Mutex::new(Vec::new()));
+        let mut first = StreamWriter::new(1, Arc::clone(&link));
+        let mut second = StreamWriter::new(2, Arc::clone(&link));
+        first.write_all(b"hello ").unwrap();
+        second.write_all(b"other").unwrap();
+        first.flush().unwrap();
+        second.flush().unwrap();
+        first.write_all(b"world").unwrap();
+        drop(first);
+        drop(second);
+
+        let wire = link.lock().unwrap().clone();
+        let demux = Demux::new();
+        let (mut one, mut two) = (demux.open(1), demux.open(2));
+        let mut reader = Cursor::new(wire);
+        while let Some((stream, payload)) = read_frame(&mut reader).unwrap() {
+            assert!(demux.deliver(stream, payload));
+        }
+        assert!(!demux.is_open(1) && !demux.is_open(2));
+        assert!(!demux.deliver(3, b"unknown".to_vec()));
+
+        let (mut a, mut b) = (String::new(), String::new());
+        one.read_to_string(&mut a).unwrap();
+        two.read_to_string(&mut b).unwrap();
+        assert_eq!(a, "hello world");
+        assert_eq!(b, "other");
+    }
+
+    #[test]
+    fn test_full_window_blocks() {
+        let demux = Arc::new(Demux::new());
+        let mut reader = demux.open(1);
+        let (sent, delivered) = std::sync::mpsc::channel();
+        let sender = std::thread::spawn({
+            let demux = Arc::clone(&demux);
+            move || {
+                for _ in 0..STREAM_WINDOW + 1 {
+                    demux.deliver(1, b"data".to_vec());
+                    sent.send(()).unwrap();
+                }
+            }
+        });
+
+        // Nothing is read, so the last frame waits for room
+        for _ in 0..STREAM_WINDOW {
+            delivered.recv_timeout(Duration::from_secs(5)).unwrap();
+        }
+        assert!(delivered.recv_timeout(Duration::from_millis(100)).is_err());
+        // Meanwhile another stream can still be opened and fed
+        let mut other = demux.open(2);
+        assert!(demux.deliver(2, b"more".to_vec()));
+        let mut buf = [0u8; 4];
+        other.read_exact(&mut buf).unwrap();
+        assert_eq!(&buf, b"more");
+
+        reader.read_exact(&mut buf).unwrap();
+        delivered.recv_timeout(Duration::from_secs(5)).unwrap();
+        sender.join().unwrap();
+    }
+
+    #[test]
+    fn test_writes_stop_once_peer_closes() {
+        let demux = Demux::new();
+        let reader = demux.open(1);
+        let link = Arc::new(Mutex::new(Vec::new()));
+        let mut writer = StreamWriter::new(1, Arc::clone(&link));
+        writer.abort_when_closed(reader.closed());
+        writer.write_all(b"before").unwrap();
+        writer.flush().unwrap();
+
+        assert!(demux.deliver(1, Vec::new()));
+        assert_eq!(writer.write(b"after").unwrap_err().kind(), io::ErrorKind::ConnectionReset);
+        drop(writer);
+
+        // What was sent before, then the close
+        let mut wire = Cursor::new(link.lock().unwrap().clone());
+        assert_eq!(read_frame(&mut wire).unwrap(), Some((1, b"before".to_vec())));
+        assert_eq!(read_frame(&mut wire).unwrap(), Some((1, Vec::new())));
+        assert_eq!(read_frame(&mut wire).unwrap(), None);
+    }
+
+    #[test]
+    fn test_stream_read_timeout() {
+        let demux = Demux::new();
+        let mut reader = demux.open(1);
+        reader.set_read_timeout(Some(Duration::from_millis(50)));
+        let mut buf = [0u8; 4];
+        assert_eq!(reader.read(&mut buf).unwrap_err().kind(), io::ErrorKind::TimedOut);
+
+        // A frame that arrives later is still read
+        assert!(demux.deliver(1, b"late".to_vec()));
+        assert_eq!(reader.read(&mut buf).unwrap(), 4);
+        assert_eq!(&buf, b"late");
+        demux.close_all();
+        assert_eq!(reader.read(&mut buf).unwrap(), 0);
+    }
+}
@@ -0,0 +1,724 @@
+use std::ffi::{OsStr, OsString};
+use std::fmt;
+use std::fs::Metadata;
+use std::io::{self, BufRead, Write};
+use std::time::UNIX_EPOCH;
+use crate::err_utils::GetError;
+use crate::{os_str_bytes, os_string_from_bytes};
+
+/// Version spoken by this build. Peers must agree on it during `HELLO`.
+pub const PROTOCOL_VERSION: u32 = 1;
+
+/// Per-chunk CRC32C on `NEXT`/`DATA` frames and a SHA-256 `DONE` digest.
+pub const CAP_CHECKSUMS: &str = "checksums";
+
+/// `STAT` requests, which may precede the transfer on the same connection.
+pub const CAP_STAT: &str = "stat";
+
+/// `LIST` requests, answered with `ENTRY` lines and a closing `OK <count>`.
+pub const CAP_LIST: &str = "list";
+
+/// `DELETE`, `RMDIR`, `MKDIR` and `RENAME` requests, each answered with `OK`.
+pub const CAP_FILE_OPS: &str = "fileops";
+
+/// Connections stay open after a transfer and serve further requests until
+/// the client sends `QUIT` or goes idle.
+pub const CAP_SESSION: &str = "session";
+
+/// After `HELLO`, everything travels in `FRAME <stream> <len>` frames and
+/// each stream is a session of its own, so transfers can run side by side.
+pub const CAP_MUX: &str = "mux";
+
+/// Optional features this build implements, negotiated per connection.
+/// A capability is active only when both peers list it in their `HELLO`.
+pub const CAPABILITIES: &[&str] = &[CAP_CHECKSUMS, CAP_STAT, CAP_LIST, CAP_FILE_OPS, CAP_SESSION, CAP_MUX];
+
+pub fn has_capability(capabilities: &[String], cap: &str) -> bool {
+    capabilities.iter().any(|c| c == cap)
+}
+
+/// Capabilities present in both lists, in the order of `ours`.
+pub fn negotiate_capabilities(ours: &[&str], theirs: &[String]) -> Vec<String> {
+    ours.iter()
+        .filter(|cap| theirs.iter().any(|other| other == *cap))
+        .map(|cap| cap.to_string())
+        .collect()
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FileKind {
+    File,
+    Dir,
+    Symlink,
+    Other,
+}
+
+impl FileKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            FileKind::File => "file",
+            FileKind::Dir => "dir",
+            FileKind::Symlink => "symlink",
+            FileKind::Other => "other",
+        }
+    }
+
+    fn parse(token: &str) -> Option<FileKind> {
+        match token {
+            "file" => Some(FileKind::File),
+            "dir" => Some(FileKind::Dir),
+            "symlink" => Some(FileKind::Symlink),
+            "other" => Some(FileKind::Other),
+            _ => None,
+        }
+    }
+}
+
+/// Metadata of a remote path as reported by `STAT`. `mtime` is in seconds
+/// since the Unix epoch and `mode` holds the Unix permission bits.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FileStat {
+    pub kind: FileKind,
+    pub size: u64,
+    pub mtime: u64,
+    pub mode: u32,
+}
+
+impl FileStat {
+    pub fn from_metadata(metadata: &Metadata) -> FileStat {
+        let file_type = metadata.file_type();
+        let kind = if file_type.is_symlink() {
+            FileKind::Symlink
+        } else if file_type.is_dir() {
+            FileKind::Dir
+        } else if file_type.is_file() {
+            FileKind::File
+        } else {
+            FileKind::Other
+        };
+
+        let mtime = metadata
+            .modified()
+            .ok()
+            .and_then(|modified| modified.duration_since(UNIX_EPOCH).ok())
+            .map_or(0, |since_epoch| since_epoch.as_secs());
+
+        FileStat { kind, size: metadata.len(), mtime, mode: permission_bits(metadata) }
+    }
+
+    fn parse_fields(fields: &[&str]) -> Option<FileStat> {
+        match fields {
+            [kind, size, mtime, mode] => Some(FileStat {
+                kind: FileKind::parse(kind)?,
+                size: size.parse().ok()?,
+                mtime: mtime.parse().ok()?,
+                mode: u32::from_str_radix(mode, 8).ok()?,
+            }),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for FileStat {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} {} {} {:o}", self.kind.as_str(), self.size, self.mtime, self.mode)
+    }
+}
+
+#[cfg(unix)]
+fn permission_bits(metadata: &Metadata) -> u32 {
+    use std::os::unix::fs::PermissionsExt;
+    metadata.permissions().mode() & 0o7777
+}
+
+// Only the read-only flag exists elsewhere; report the usual Unix defaults
+#[cfg(not(unix))]
+fn permission_bits(metadata: &Metadata) -> u32 {
+    match (metadata.is_dir(), metadata.permissions().readonly()) {
+        (true, false) => 0o755,
+        (true, true) => 0o555,
+        (false, false) => 0o644,
+        (false, true) => 0o444,
+    }
+}
+
+/// A command sent by the client. Every request is a single line on the wire,
+/// with paths percent-encoded so they survive whitespace splitting.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Request {
+    /// First request on a connection; `user` comes from a `remcp://user@host` address.
+    Hello { version: u32, capabilities: Vec<String>, user: Option<String> },
+    Get { path: OsString, offset: u64 },
+    /// Carries no offset: the server answers `OK <have>` and the client resumes from there.
+    Put { path: OsString, total_size: u64 },
+    Stat { path: OsString },
+    List { path: OsString, recursive: bool, pattern: Option<OsString> },
+    Delete { path: OsString },
+    Rmdir { path: OsString },
+    Mkdir { path: OsString },
+    Rename { from: OsString, to: OsString },
+    Quit,
+    /// Frames an upload chunk when checksums are negotiated.
+    Data { size: usize, crc: u32 },
+    /// Closes a checksummed upload with the whole file's digest.
+    Done { digest: String },
+}
+
+/// A reply sent by the server.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Response {
+    Hello { version: u32, capabilities: Vec<String> },
+    Ok(Option<u64>),
+    /// A chunk of raw bytes that follows (GET) or that the server is ready to
+    /// receive (PUT); for GET, with its CRC32C when checksums are negotiated.
+    Next { size: usize, crc: Option<u32> },
+    Done { digest: String },
+    Stat(FileStat),
+    /// One line of a `LIST` reply, with the path relative to the listed directory.
+    Entry { path: OsString, stat: FileStat },
+    /// The client's place in the admission queue, sent before the `HELLO` reply.
+    Queued(usize),
+    Err(GetError),
+}
+
+fn read_line<R: BufRead>(reader: &mut R) -> io::Result<String> {
+    let mut line = String::new();
+    if reader.read_line(&mut line)? == 0 {
+        return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "Connection closed"));
+    }
+    Ok(line.trim_end().to_string())
+}
+
+fn invalid_data(err: GetError) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, err)
+}
+
+fn parse_number<T: std::str::FromStr>(token: &str) -> Result<T, GetError> {
+    token.parse().map_err(|_| GetError::InvalidCommand)
+}
+
+fn parse_crc(token: &str) -> Option<u32> {
+    u32::from_str_radix(token, 16).ok()
+}
+
+fn is_digest(token: &str) -> bool {
+    token.len() == 64 && token.bytes().all(|b| b.is_ascii_hexdigit())
+}
+
+fn parse_capabilities(token: &str) -> Vec<String> {
+    if token == "-" {
+        return Vec::new();
+    }
+    token.split(',').filter(|cap| !cap.is_empty()).map(str::to_string).collect()
+}
+
+fn format_capabilities(capabilities: &[String]) -> String {
+    if capabilities.is_empty() {
+        "-".to_string()
+    } else {
+        capabilities.join(",")
+    }
+}
+
+fn is_unreserved(byte: u8) -> bool {
+    byte.is_ascii_alphanumeric() || matches!(byte, b'-' | b'.' | b'_' | b'~' | b'/' | b'\\' | b':')
+}
+
+/// Percent-encodes a path into a single whitespace-free ASCII token. Every
+/// byte outside the unreserved set, including spaces, non-ASCII UTF-8 and
+/// non-UTF-8 bytes, becomes `%XX`.
+pub fn encode_path(path: &OsStr) -> String {
+    let mut encoded = String::new();
+    for &byte in os_str_bytes(path).iter() {
+        if is_unreserved(byte) {
+            encoded.push(byte as char);
+        } else {
+            encoded.push_str(&format!("%{:02X}", byte));
+        }
+    }
+    encoded
+}
+
+pub fn decode_path(token: &str) -> Result<OsString, GetError> {
+    let bytes = token.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] == b'%' {
+            // `from_str_radix` alone would also take a sign, as in `%+1`
+            let hex = token.get(i + 1..i + 3).filter(|hex| hex.bytes().all(|b| b.is_ascii_hexdigit()));
+            let hex = hex.ok_or(GetError::InvalidCommand)?;
+            let byte = u8::from_str_radix(hex, 16).map_err(|_| GetError::InvalidCommand)?;
+            decoded.push(byte);
+            i += 3;
+        } else {
+            decoded.push(bytes[i]);
+            i += 1;
+        }
+    }
+    Ok(os_string_from_bytes(decoded))
+}
+
+impl Request {
+    pub fn parse(line: &str) -> Result<Request, GetError> {
+        let parts: Vec<&str> = line.split_whitespace().collect();
+        if parts.is_empty() {
+            return Err(GetError::InvalidCommand);
+        }
+
+        let command = parts[0].to_uppercase();
+        // Arguments a request takes at most, counting the optional ones
+        let most = match command.as_str() {
+            "QUIT" => 0,
+            "STAT" | "DELETE" | "RMDIR" | "MKDIR" | "DONE" => 1,
+            "GET" | "PUT" | "RENAME" | "DATA" => 2,
+            "HELLO" | "LIST" => 3,
+            _ => return Err(GetError::UnknownCommand),
+        };
+        if parts.len() - 1 > most {
+            return Err(GetError::InvalidCommand);
+        }
+
+        match command.as_str() {
+            "HELLO" => {
+                if parts.len() < 3 {
+                    return Err(GetError::MissingArguments);
+                }
+                let user = match parts.get(3) {
+                    Some(token) => Some(decode_path(token)?.to_string_lossy().into_owned()),
+                    None => None,
+                };
+                Ok(Request::Hello {
+                    version: parse_number(parts[1])?,
+                    capabilities: parse_capabilities(parts[2]),
+                    user,
+                })
+            }
+            "GET" => {
+                if parts.len() < 3 {
+                    return Err(GetError::MissingArguments);
+                }
+                Ok(Request::Get {
+                    path: decode_path(parts[1])?,
+                    offset: parse_number(parts[2])?,
+                })
+            }
+            "PUT" => {
+                if parts.len() < 3 {
+                    return Err(GetError::MissingArguments);
+                }
+                Ok(Request::Put {
+                    path: decode_path(parts[1])?,
+                    total_size: parse_number(parts[2])?,
+                })
+            }
+            "STAT" => {
+                if parts.len() < 2 {
+                    return Err(GetError::MissingArguments);
+                }
+                Ok(Request::Stat { path: decode_path(parts[1])? })
+            }
+            "LIST" => {
+                if parts.len() < 3 {
+                    return Err(GetError::MissingArguments);
+                }
+                let recursive = match parts[2] {
+                    "0" => false,
+                    "1" => true,
+                    _ => return Err(GetError::InvalidCommand),
+                };
+                Ok(Request::List {
+                    path: decode_path(parts[1])?,
+                    recursive,
+                    pattern: parts.get(3).map(|pattern| decode_path(pattern)).transpose()?,
+                })
+            }
+            "DELETE" | "RMDIR" | "MKDIR" => {
+                if parts.len() < 2 {
+                    return Err(GetError::MissingArguments);
+                }
+                let path = decode_path(parts[1])?;
+                Ok(match command.as_str() {
+                    "DELETE" => Request::Delete { path },
+                    "RMDIR" => Request::Rmdir { path },
+                    _ => Request::Mkdir { path },
+                })
+            }
+            "RENAME" => {
+                if parts.len() < 3 {
+                    return Err(GetError::MissingArguments);
+                }
+                Ok(Request::Rename { from: decode_path(parts[1])?, to: decode_path(parts[2])? })
+            }
+            "QUIT" => Ok(Request::Quit),
+            "DATA" => {
+                if parts.len() < 3 {
+                    return Err(GetError::MissingArguments);
+                }
+                Ok(Request::Data {
+                    size: parse_number(parts[1])?,
+                    crc: parse_crc(parts[2]).ok_or(GetError::InvalidCommand)?,
+                })
+            }
+            "DONE" => {
+                if parts.len() < 2 {
+                    return Err(GetError::MissingArguments);
+                }
+                if !is_digest(parts[1]) {
+                    return Err(GetError::InvalidCommand);
+                }
+                Ok(Request::Done { digest: parts[1].to_lowercase() })
+            }
+            _ => unreachable!("unknown commands are turned away above"),
+        }
+    }
+
+    /// Reads one request line. Malformed requests come back as an
+    /// `InvalidData` error carrying the `GetError` to report to the peer.
+    pub fn decode<R: BufRead>(reader: &mut R) -> io::Result<Request> {
+        let line = read_line(reader)?;
+        Request::parse(&line).map_err(invalid_data)
+    }
+
+    pub fn encode<W: Write>(&self, writer: &mut W) -> io::Result<()> {
+        writeln!(writer, "{}", self)?;
+        writer.flush()
+    }
+}
+
+impl fmt::Display for Request {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Request::Hello { version, capabilities, user } => {
+                write!(f, "HELLO {} {}", version, format_capabilities(capabilities))?;
+                match user {
+                    Some(user) => write!(f, " {}", encode_path(OsStr::new(user))),
+                    None => Ok(()),
+                }
+            }
+            Request::Get { path, offset } => write!(f, "GET {} {}", encode_path(path), offset),
+            Request::Put { path, total_size } => write!(f, "PUT {} {}", encode_path(path), total_size),
+            Request::Stat { path } => write!(f, "STAT {}", encode_path(path)),
+            Request::List { path, recursive, pattern } => {
+                write!(f, "LIST {} {}", encode_path(path), u8::from(*recursive))?;
+                match pattern {
+                    Some(pattern) => write!(f, " {}", encode_path(pattern)),
+                    None => Ok(()),
+                }
+            }
+            Request::Delete { path } => write!(f, "DELETE {}", encode_path(path)),
+            Request::Rmdir { path } => write!(f, "RMDIR {}", encode_path(path)),
+            Request::Mkdir { path } => write!(f, "MKDIR {}", encode_path(path)),
+            Request::Rename { from, to } => write!(f, "RENAME {} {}", encode_path(from), encode_path(to)),
+            Request::Quit => write!(f, "QUIT"),
+            Request::Data { size, crc } => write!(f, "DATA {} {:08x}", size, crc),
+            Request::Done { digest } => write!(f, "DONE {}", digest),
+        }
+    }
+}
+
+impl Response {
+    pub fn parse(line: &str) -> Result<Response, GetError> {
+        if let Some(err) = line.strip_prefix("ERR ") {
+            let Ok(err) = err.parse::<GetError>();
+            return Ok(Response::Err(err));
+        }
+
+        let parts: Vec<&str> = line.split_whitespace().collect();
+        match parts.first().copied() {
+            Some("HELLO") => {
+                if parts.len() == 3 {
+                    if let Ok(version) = parts[1].parse() {
+                        return Ok(Response::Hello {
+                            version,
+                            capabilities: parse_capabilities(parts[2]),
+                        });
+                    }
+                }
+                Err(GetError::Other("Invalid HELLO response format".to_string()))
+            }
+            Some("OK") => match parts.get(1) {
+                None => Ok(Response::Ok(None)),
+                Some(size) => size
+                    .parse()
+                    .map(|size| Response::Ok(Some(size)))
+                    .map_err(|_| GetError::Other("Invalid OK response format".to_string())),
+            },
+            Some("NEXT") => {
+                if let Some(Ok(size)) = parts.get(1).map(|size| size.parse()) {
+                    match parts.len() {
+                        2 => return Ok(Response::Next { size, crc: None }),
+                        3 => {
+                            if let Some(crc) = parse_crc(parts[2]) {
+                                return Ok(Response::Next { size, crc: Some(crc) });
+                            }
+                        }
+                        _ => {}
+                    }
+                }
+                Err(GetError::Other("Invalid NEXT command format".to_string()))
+            }
+            Some("DONE") => match parts.get(1) {
+                Some(digest) if parts.len() == 2 && is_digest(digest) => {
+                    Ok(Response::Done { digest: digest.to_lowercase() })
+                }
+                _ => Err(GetError::Other("Invalid DONE response format".to_string())),
+            },
+            Some("ENTRY") => parts
+                .get(1)
+                .and_then(|path| Some((decode_path(path).ok()?, FileStat::parse_fields(&parts[2..])?)))
+                .map(|(path, stat)| Response::Entry { path, stat })
+                .ok_or_else(|| GetError::Other("Invalid ENTRY response format".to_string())),
+            Some("STAT") => FileStat::parse_fields(&parts[1..])
+                .map(Response::Stat)
+                .ok_or_else(|| GetError::Other("Invalid STAT response format".to_string())),
+            Some("QUEUED") => match parts.as_slice() {
+                [_, position] => position
+                    .parse()
+                    .map(Response::Queued)
+                    .map_err(|_| GetError::Other("Invalid QUEUED response format".to_string())),
+                _ => Err(GetError::Other("Invalid QUEUED response format".to_string())),
+            },
+            _ => Err(GetError::Other("Invalid response".to_string())),
+        }
+    }
+
+    /// Reads one response line. `ERR` replies decode successfully as
+    /// `Response::Err`; only unparseable lines are reported as errors.
+    pub fn decode<R: BufRead>(reader: &mut R) -> io::Result<Response> {
+        let line = read_line(reader)?;
+        Response::parse(&line).map_err(invalid_data)
+    }
+
+    pub fn encode<W: Write>(&self, writer: &mut W) -> io::Result<()> {
+        writeln!(writer, "{}", self)?;
+        writer.flush()
+    }
+}
+
+impl fmt::Display for Response {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Response::Hello { version, capabilities } => {
+                write!(f, "HELLO {} {}", version, format_capabilities(capabilities))
+            }
+            Response::Ok(None) => write!(f, "OK"),
+            Response::Ok(Some(size)) => write!(f, "OK {}", size),
+            Response::Next { size, crc: None } => write!(f, "NEXT {}", size),
+            Response::Next { size, crc: Some(crc) } => write!(f, "NEXT {} {:08x}", size, crc),
+            Response::Done { digest } => write!(f, "DONE {}", digest),
+            Response::Stat(stat) => write!(f, "STAT {}", stat),
+            Response::Entry { path, stat } => write!(f, "ENTRY {} {}", encode_path(path), stat),
+            Response::Queued(position) => write!(f, "QUEUED {}", position),
+            // Error text may echo client paths; keep it on one line
+            Response::Err(err) => write!(f, "ERR {}", err.to_string().replace(['\r', '\n'], " ")),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+    use crate::err_utils::BusyHint;
+
+    #[test]
+    fn test_request_round_trip() {
+        let requests = vec![
+            Request::Hello { version: PROTOCOL_VERSION, capabilities: vec![], user: None },
+            Request::Hello { version: 2, capabilities: vec!["a".to_string(), "b".to_string()], user: None },
+            Request::Hello { version: PROTOCOL_VERSION, capabilities: vec![], user: Some("ana maria".to_string()) },
+            Request::Get { path: "dir/file.txt".into(), offset: 0 },
+            Request::Get { path: "Área de Trabalho/file name.bin".into(), offset: 4096 },
+            Request::Put { path: "upload.bin".into(), total_size: 20 },
+            Request::Stat { path: "dir/file name.txt".into() },
+            Request::List { path: ".".into(), recursive: false, pattern: None },
+            Request::List { path: "my dir".into(), recursive: true, pattern: Some("*.[ch]".into()) },
+            Request::Delete { path: "old file.txt".into() },
+            Request::Rmdir { path: "empty dir".into() },
+            Request::Mkdir { path: "a/b/c".into() },
+            Request::Rename { from: "draft.txt".into(), to: "final version.txt".into() },
+            Request::Quit,
+            Request::Data { size: 51, crc: 0x0000_beef },
+            Request::Done { digest: "ab".repeat(32) },
+        ];
+
+        let mut wire = Vec::new();
+        for request in &requests {
+            request.encode(&mut wire).unwrap();
+        }
+
+        let mut reader = Cursor::new(wire);
+        for request in &requests {
+            assert_eq!(&Request::decode(&mut reader).unwrap(), request);
+        }
+        let eof = Request::decode(&mut reader).unwrap_err();
+        assert_eq!(eof.kind(), io::ErrorKind::UnexpectedEof);
+    }
+
+    #[test]
+    fn test_request_errors() {
+        assert_eq!(Request::parse(""), Err(GetError::InvalidCommand));
+        assert_eq!(Request::parse("GET file"), Err(GetError::MissingArguments));
+        assert_eq!(Request::parse("PUT file"), Err(GetError::MissingArguments));
+        assert_eq!(Request::parse("GET file abc"), Err(GetError::InvalidCommand));
+        assert_eq!(Request::parse("CHMOD file 644"), Err(GetError::UnknownCommand));
+        assert_eq!(Request::parse("RENAME file"), Err(GetError::MissingArguments));
+        assert_eq!(Request::parse("GET a 0 junk"), Err(GetError::InvalidCommand));
+        assert_eq!(Request::parse("QUIT now"), Err(GetError::InvalidCommand));
+        assert_eq!(Request::parse("LIST dir 0 *.txt extra"), Err(GetError::InvalidCommand));
+        assert_eq!(
+            Request::parse("get file 5"),
+            Ok(Request::Get { path: "file".into(), offset: 5 })
+        );
+        assert_eq!(Request::parse("GET file%2 0"), Err(GetError::InvalidCommand));
+        assert_eq!(Request::parse("GET file%zz 0"), Err(GetError::InvalidCommand));
+        assert_eq!(Request::parse("GET file%+1 0"), Err(GetError::InvalidCommand));
+        assert_eq!(Request::parse("GET file%-1 0"), Err(GetError::InvalidCommand));
+
+        let err = Request::decode(&mut Cursor::new("GET\n")).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        assert_eq!(GetError::from_io(&err), Some(&GetError::MissingArguments));
+    }
+
+    #[test]
+    fn test_negotiate_capabilities() {
+        let theirs = vec!["zeta".to_string(), "beta".to_string(), "alpha".to_string()];
+        assert_eq!(
+            negotiate_capabilities(&["alpha", "beta", "gamma"], &theirs),
+            vec!["alpha".to_string(), "beta".to_string()]
+        );
+        assert!(negotiate_capabilities(&["alpha"], &[]).is_empty());
+        assert_eq!(Request::parse("HELLO 1"), Err(GetError::MissingArguments));
+        assert_eq!(Request::parse("HELLO x -"), Err(GetError::InvalidCommand));
+    }
+
+    #[test]
+    fn test_path_encoding() {
+        let path = OsStr::new("/Users/gabri/OneDrive/Área de Trabalho/100% done.txt");
+        let encoded = encode_path(path);
+        assert_eq!(
+            encoded,
+            "/Users/gabri/OneDrive/%C3%81rea%20de%20Trabalho/100%25%20done.txt"
+        );
+        assert!(!encoded.contains(char::is_whitespace));
+        assert_eq!(decode_path(&encoded).unwrap(), path);
+
+        let request = Request::Put { path: path.into(), total_size: 1 };
+        assert_eq!(Request::parse(&request.to_string()), Ok(request));
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn test_path_encoding_non_utf8() {
+        use std::os::unix::ffi::OsStrExt;
+
+        let path = OsStr::from_bytes(b"dir/\xff\xfe name\n.bin");
+        let encoded = encode_path(path);
+        assert_eq!(encoded, "dir/%FF%FE%20name%0A.bin");
+        assert_eq!(decode_path(&encoded).unwrap(), path);
+    }
+
+    #[test]
+    fn test_response_round_trip() {
+        let responses = vec![
+            Response::Hello { version: PROTOCOL_VERSION, capabilities: vec!["a".to_string()] },
+            Response::Ok(None),
+            Response::Ok(Some(1234)),
+            Response::Next { size: 64, crc: None },
+            Response::Next { size: 64, crc: Some(0xe306_9283) },
+            Response::Done { digest: "0f".repeat(32) },
+            Response::Stat(FileStat { kind: FileKind::File, size: 1234, mtime: 1_700_000_000, mode: 0o644 }),
+            Response::Stat(FileStat { kind: FileKind::Dir, size: 0, mtime: 0, mode: 0o4755 }),
+            Response::Entry {
+                path: "sub dir/a.txt".into(),
+                stat: FileStat { kind: FileKind::File, size: 5, mtime: 1, mode: 0o600 },
+            },
+            Response::Queued(3),
+            Response::Err(GetError::ServerBusy(None)),
+            Response::Err(GetError::FileError("No such file or directory".to_string())),
+        ];
+
+        let mut wire = Vec::new();
+        for response in &responses {
+            response.encode(&mut wire).unwrap();
+        }
+
+        let mut reader = Cursor::new(wire);
+        for response in &responses {
+            assert_eq!(&Response::decode(&mut reader).unwrap(), response);
+        }
+    }
+
+    #[test]
+    fn test_server_response() {
+        match Response::parse("ERR Invalid command") {
+            Ok(Response::Err(err)) => assert_eq!(err.to_string(), "Invalid command"),
+            _ => panic!("Expected an error response"),
+        }
+
+        match Response::parse("OK") {
+            Ok(Response::Ok(None)) => {}
+            _ => panic!("Expected an OK response"),
+        }
+    }
+
+    #[test]
+    fn test_server_response_next() {
+        match Response::parse("NEXT 64") {
+            Ok(Response::Next { size, crc: None }) => assert_eq!(size, 64),
+            _ => panic!("Expected a NEXT 64 response"),
+        }
+
+        assert_eq!(
+            Response::parse("NEXT 64 e3069283"),
+            Ok(Response::Next { size: 64, crc: Some(0xe306_9283) })
+        );
+        assert!(Response::parse("NEXT 64 xyz").is_err());
+        assert!(Response::parse("DONE abc").is_err());
+        assert!(Response::parse("QUEUED").is_err());
+        assert!(Response::parse("QUEUED first").is_err());
+
+        match Response::parse("NEXT abc") {
+            Err(err) => {
+                assert_eq!(err.to_string(), "Other error: Invalid NEXT command format")
+            }
+            _ => panic!("Expected an error for invalid NEXT format"),
+        }
+
+        match Response::parse("UNKNOWN") {
+            Err(err) => {
+                assert!(err.to_string().contains("Invalid response"))
+            },
+            _ => panic!("Expected invalid response error"),
+        }
+    }
+
+    #[test]
+    fn test_stat_response() {
+        assert_eq!(
+            Response::parse("STAT file 12 1700000000 640"),
+            Ok(Response::Stat(FileStat { kind: FileKind::File, size: 12, mtime: 1_700_000_000, mode: 0o640 }))
+        );
+        assert!(Response::parse("STAT file 12 1700000000").is_err());
+        assert!(Response::parse("STAT pipe 12 1700000000 640").is_err());
+        assert!(Response::parse("STAT file 12 1700000000 999").is_err());
+        assert_eq!(Request::parse("STAT"), Err(GetError::MissingArguments));
+        assert_eq!(Request::parse("LIST dir"), Err(GetError::MissingArguments));
+        assert_eq!(Request::parse("LIST dir yes"), Err(GetError::InvalidCommand));
+        assert!(Response::parse("ENTRY a.txt file 1 2").is_err());
+    }
+
+    #[test]
+    fn test_server_busy_spelling() {
+        // Both binaries used to disagree on this string; there is now only one.
+        let wire = Response::Err(GetError::ServerBusy(None)).to_string();
+        assert_eq!(wire, "ERR Server is busy");
+        assert_eq!(Response::parse(&wire), Ok(Response::Err(GetError::ServerBusy(None))));
+
+        let hint = GetError::ServerBusy(Some(BusyHint { retry_after: 4, position: 2 }));
+        let wire = Response::Err(hint.clone()).to_string();
+        assert_eq!(wire, "ERR Server is busy (retry after 4s, queue position 2)");
+        assert_eq!(Response::parse(&wire), Ok(Response::Err(hint)));
+    }
+}
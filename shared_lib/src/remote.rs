@@ -0,0 +1,193 @@
+use std::ffi::{OsStr, OsString};
+use crate::{decode_path, os_str_bytes, os_string_from_bytes};
+
+/// Scheme of the URI form of a remote path.
+pub const URI_SCHEME: &str = "remcp://";
+
+/// A remote path given on the command line, as `remcp://[user@]host[:port]/path`
+/// or scp-style as `[user@]host:[port:]path`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RemoteSpec {
+    pub user: Option<String>,
+    /// Without brackets, even for IPv6 literals.
+    pub host: String,
+    pub port: Option<u16>,
+    /// Relative to the served root; `.` when the spec names no path.
+    pub path: OsString,
+}
+
+impl RemoteSpec {
+    /// `host:port` to connect to, with IPv6 literals bracketed.
+    pub fn address(&self, default_port: u16) -> String {
+        let port = self.port.unwrap_or(default_port);
+        if self.host.contains(':') {
+            format!("[{}]:{}", self.host, port)
+        } else {
+            format!("{}:{}", self.host, port)
+        }
+    }
+}
+
+/// Parses `arg` as a remote path. Returns `None` for local paths, and an
+/// error for arguments that are clearly meant as remote but malformed.
+///
+/// An argument is local unless it is a `remcp://` URI or has a host before
+/// its first `:`. A `/` or `\` before that colon makes it local, so
+/// `./notes:v2.txt` escapes a local name with a colon, and so does a drive
+/// letter as in `C:\file` or `C:/file`.
+pub fn parse_remote(arg: &OsStr) -> Option<Result<RemoteSpec, String>> {
+    let bytes = os_str_bytes(arg);
+    if let Some(rest) = bytes.strip_prefix(URI_SCHEME.as_bytes()) {
+        return Some(parse_uri(rest));
+    }
+
+    let colon = if bytes.first() == Some(&b'[') {
+        let end = bytes.iter().position(|&b| b == b']')?;
+        match bytes.get(end + 1) {
+            Some(b':') => end + 1,
+            _ => return None,
+        }
+    } else {
+        bytes.iter().position(|&b| b == b':')?
+    };
+
+    let prefix = &bytes[..colon];
+    if prefix.iter().any(|&b| b == b'/' || b == b'\\') {
+        return None;
+    }
+    let is_drive = prefix.len() == 1
+        && prefix[0].is_ascii_alphabetic()
+        && matches!(bytes.get(colon + 1), Some(b'/') | Some(b'\\'));
+    if is_drive {
+        return None;
+    }
+
+    Some(parse_scp(prefix, &bytes[colon + 1..]))
+}
+
+/// `[user@]host[:port]/path`, with the path percent-encoded.
+fn parse_uri(rest: &[u8]) -> Result<RemoteSpec, String> {
+    let slash = rest.iter().position(|&b| b == b'/').unwrap_or(rest.len());
+    let (user, host, port) = parse_authority(&rest[..slash])?;
+
+    let encoded = String::from_utf8_lossy(rest.get(slash + 1..).unwrap_or_default());
+    let path = decode_path(&encoded).map_err(|_| format!("invalid escape in path '{}'", encoded))?;
+    Ok(RemoteSpec { user, host, port, path: or_root(path) })
+}
+
+/// `[user@]host` before the colon, then `[port:]path` after it.
+fn parse_scp(prefix: &[u8], rest: &[u8]) -> Result<RemoteSpec, String> {
+    let (user, host, _) = parse_authority(prefix)?;
+
+    let digits = rest.iter().take_while(|b| b.is_ascii_digit()).count();
+    let (port, path) = match rest.get(digits) {
+        Some(b':') if digits > 0 => (Some(parse_port(&rest[..digits])?), &rest[digits + 1..]),
+        _ => (None, rest),
+    };
+    Ok(RemoteSpec { user, host, port, path: or_root(os_string_from_bytes(path.to_vec())) })
+}
+
+type Authority = (Option<String>, String, Option<u16>);
+
+fn parse_authority(authority: &[u8]) -> Result<Authority, String> {
+    let (user, host_port) = match authority.iter().rposition(|&b| b == b'@') {
+        Some(at) => (Some(&authority[..at]), &authority[at + 1..]),
+        None => (None, authority),
+    };
+    let user = match user {
+        Some([]) => return Err("empty user name".to_string()),
+        Some(user) => Some(String::from_utf8_lossy(user).into_owned()),
+        None => None,
+    };
+
+    let (host, port) = if let Some(bracketed) = host_port.strip_prefix(b"[") {
+        let end = bracketed.iter().position(|&b| b == b']').ok_or("unterminated '[' in host")?;
+        match &bracketed[end + 1..] {
+            [] => (&bracketed[..end], None),
+            [b':', port @ ..] => (&bracketed[..end], Some(parse_port(port)?)),
+            _ => return Err("unexpected text after ']'".to_string()),
+        }
+    } else {
+        match host_port.iter().position(|&b| b == b':') {
+            Some(colon) => (&host_port[..colon], Some(parse_port(&host_port[colon + 1..])?)),
+            None => (host_port, None),
+        }
+    };
+
+    if host.is_empty() {
+        return Err("missing host".to_string());
+    }
+    Ok((user, String::from_utf8_lossy(host).into_owned(), port))
+}
+
+fn parse_port(port: &[u8]) -> Result<u16, String> {
+    std::str::from_utf8(port)
+        .ok()
+        .and_then(|port| port.parse().ok())
+        .ok_or_else(|| format!("invalid port '{}'", String::from_utf8_lossy(port)))
+}
+
+fn or_root(path: OsString) -> OsString {
+    if path.is_empty() {
+        OsString::from(".")
+    } else {
+        path
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn remote(arg: &str) -> RemoteSpec {
+        parse_remote(OsStr::new(arg)).expect("should be remote").expect("should parse")
+    }
+
+    fn spec(user: Option<&str>, host: &str, port: Option<u16>, path: &str) -> RemoteSpec {
+        RemoteSpec { user: user.map(str::to_string), host: host.to_string(), port, path: path.into() }
+    }
+
+    #[test]
+    fn test_uri() {
+        assert_eq!(remote("remcp://ana@server:9000/dir/file.txt"), spec(Some("ana"), "server", Some(9000), "dir/file.txt"));
+        assert_eq!(remote("remcp://server/my%20file.txt"), spec(None, "server", None, "my file.txt"));
+        assert_eq!(remote("remcp://[::1]:7979/a:b"), spec(None, "::1", Some(7979), "a:b"));
+        assert_eq!(remote("remcp://server"), spec(None, "server", None, "."));
+        assert_eq!(remote("remcp://server/"), spec(None, "server", None, "."));
+
+        for bad in ["remcp:///path", "remcp://host:port/x", "remcp://@host/x", "remcp://[::1/x", "remcp://h/%zz"] {
+            assert!(matches!(parse_remote(OsStr::new(bad)), Some(Err(_))), "{} should be rejected", bad);
+        }
+    }
+
+    #[test]
+    fn test_scp_style() {
+        assert_eq!(remote("server:dir/file.txt"), spec(None, "server", None, "dir/file.txt"));
+        assert_eq!(remote("ana@server:file"), spec(Some("ana"), "server", None, "file"));
+        assert_eq!(remote("server:7979:file"), spec(None, "server", Some(7979), "file"));
+        assert_eq!(remote("[::1]:7979:file"), spec(None, "::1", Some(7979), "file"));
+        assert_eq!(remote("[::1]:file"), spec(None, "::1", None, "file"));
+        assert_eq!(remote("server:notes:v2.txt"), spec(None, "server", None, "notes:v2.txt"));
+        assert_eq!(remote("server:2024"), spec(None, "server", None, "2024"));
+        // An empty path names the served root instead of an empty token
+        assert_eq!(remote("server:"), spec(None, "server", None, "."));
+        assert_eq!(remote("server:7979:"), spec(None, "server", Some(7979), "."));
+
+        assert!(matches!(parse_remote(OsStr::new(":file")), Some(Err(_))));
+        assert!(matches!(parse_remote(OsStr::new("server:99999:file")), Some(Err(_))));
+    }
+
+    #[test]
+    fn test_local_paths() {
+        for local in ["file.txt", "./notes:v2.txt", "dir/a:b", "/abs/a:b", "C:\\file", "C:/file", "d:\\x", "[::1]", "..\\a:b"] {
+            assert_eq!(parse_remote(OsStr::new(local)), None, "{} should be local", local);
+        }
+    }
+
+    #[test]
+    fn test_address() {
+        assert_eq!(remote("server:file").address(7878), "server:7878");
+        assert_eq!(remote("server:9000:file").address(7878), "server:9000");
+        assert_eq!(remote("[fe80::1]:file").address(7878), "[fe80::1]:7878");
+    }
+}